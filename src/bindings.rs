@@ -0,0 +1,431 @@
+use std::{
+    env,
+    error::Error,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use serde::de::{value::Error as ValueError, value::StrDeserializer, IntoDeserializer};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::gb::{joypad::Button, ppu::Layer};
+
+/// Everything a key can be bound to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Joypad(Button),
+    Turbo(Button),
+    Exit,
+    Pause,
+    FrameAdvance,
+    Reset,
+    Screenshot,
+    Record,
+    RecordMovie,
+    SaveState,
+    LoadState,
+    PrevSlot,
+    NextSlot,
+    Rewind,
+    FastForward,
+    ToggleFastForward,
+    Slower,
+    Faster,
+    ToggleLayer(Layer),
+    CyclePalette,
+    TileMap,
+    Oam,
+}
+
+/// Names used in the config file and on the command line, with the default keys
+const ACTIONS: [(&str, Action, VirtualKeyCode); 32] = [
+    ("up", Action::Joypad(Button::Up), VirtualKeyCode::W),
+    ("down", Action::Joypad(Button::Down), VirtualKeyCode::S),
+    ("left", Action::Joypad(Button::Left), VirtualKeyCode::A),
+    ("right", Action::Joypad(Button::Right), VirtualKeyCode::D),
+    ("a", Action::Joypad(Button::A), VirtualKeyCode::Period),
+    ("b", Action::Joypad(Button::B), VirtualKeyCode::Comma),
+    (
+        "start",
+        Action::Joypad(Button::Start),
+        VirtualKeyCode::Return,
+    ),
+    (
+        "select",
+        Action::Joypad(Button::Select),
+        VirtualKeyCode::RShift,
+    ),
+    ("turbo_a", Action::Turbo(Button::A), VirtualKeyCode::Slash),
+    ("turbo_b", Action::Turbo(Button::B), VirtualKeyCode::M),
+    ("exit", Action::Exit, VirtualKeyCode::Escape),
+    ("pause", Action::Pause, VirtualKeyCode::P),
+    ("frame_advance", Action::FrameAdvance, VirtualKeyCode::N),
+    ("reset", Action::Reset, VirtualKeyCode::R),
+    ("screenshot", Action::Screenshot, VirtualKeyCode::F12),
+    ("record", Action::Record, VirtualKeyCode::F10),
+    ("record_movie", Action::RecordMovie, VirtualKeyCode::F9),
+    ("save_state", Action::SaveState, VirtualKeyCode::F5),
+    ("load_state", Action::LoadState, VirtualKeyCode::F8),
+    ("prev_slot", Action::PrevSlot, VirtualKeyCode::F6),
+    ("next_slot", Action::NextSlot, VirtualKeyCode::F7),
+    ("rewind", Action::Rewind, VirtualKeyCode::Back),
+    ("fast_forward", Action::FastForward, VirtualKeyCode::Tab),
+    (
+        "toggle_fast_forward",
+        Action::ToggleFastForward,
+        VirtualKeyCode::Grave,
+    ),
+    ("slower", Action::Slower, VirtualKeyCode::Minus),
+    ("faster", Action::Faster, VirtualKeyCode::Equals),
+    (
+        "background",
+        Action::ToggleLayer(Layer::Background),
+        VirtualKeyCode::Key1,
+    ),
+    (
+        "window",
+        Action::ToggleLayer(Layer::Window),
+        VirtualKeyCode::Key2,
+    ),
+    (
+        "sprites",
+        Action::ToggleLayer(Layer::Obj),
+        VirtualKeyCode::Key3,
+    ),
+    ("palette", Action::CyclePalette, VirtualKeyCode::C),
+    ("tile_map", Action::TileMap, VirtualKeyCode::F1),
+    ("oam", Action::Oam, VirtualKeyCode::F2),
+];
+
+/// Default controller inputs, the face buttons sit like on a Game Boy with A on the right
+const PAD_DEFAULTS: [(&str, &[&str]); 13] = [
+    ("up", &["DPadUp", "LeftStickY+"]),
+    ("down", &["DPadDown", "LeftStickY-"]),
+    ("left", &["DPadLeft", "LeftStickX-"]),
+    ("right", &["DPadRight", "LeftStickX+"]),
+    ("a", &["East"]),
+    ("b", &["South"]),
+    ("start", &["Start"]),
+    ("select", &["Select"]),
+    ("turbo_a", &["North"]),
+    ("turbo_b", &["West"]),
+    ("pause", &["Mode"]),
+    ("rewind", &["LeftTrigger"]),
+    ("fast_forward", &["RightTrigger"]),
+];
+
+/// Controller buttons and sticks by their gilrs names
+const PAD_BUTTONS: [&str; 19] = [
+    "South",
+    "East",
+    "North",
+    "West",
+    "C",
+    "Z",
+    "LeftTrigger",
+    "LeftTrigger2",
+    "RightTrigger",
+    "RightTrigger2",
+    "Select",
+    "Start",
+    "Mode",
+    "LeftThumb",
+    "RightThumb",
+    "DPadUp",
+    "DPadDown",
+    "DPadLeft",
+    "DPadRight",
+];
+const PAD_AXES: [&str; 8] = [
+    "LeftStickX",
+    "LeftStickY",
+    "LeftZ",
+    "RightStickX",
+    "RightStickY",
+    "RightZ",
+    "DPadX",
+    "DPadY",
+];
+
+/// A controller button, or a stick pushed in one direction past the dead zone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadInput {
+    Button(&'static str),
+    /// Axis and whether it is pushed towards positive values, up or right for sticks
+    Axis(&'static str, bool),
+}
+
+impl PadInput {
+    /// "South" or a stick direction like "LeftStickX-"
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(button) = PAD_BUTTONS.iter().find(|b| **b == name) {
+            return Some(PadInput::Button(button));
+        }
+        let (axis, positive) = match name.split_at(name.len().saturating_sub(1)) {
+            (axis, "+") => (axis, true),
+            (axis, "-") => (axis, false),
+            _ => return None,
+        };
+        PAD_AXES
+            .iter()
+            .find(|a| **a == axis)
+            .map(|axis| PadInput::Axis(axis, positive))
+    }
+}
+
+impl std::fmt::Display for PadInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PadInput::Button(button) => write!(f, "{}", button),
+            PadInput::Axis(axis, positive) => {
+                write!(f, "{}{}", axis, if *positive { '+' } else { '-' })
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    FileError(Box<dyn Error>),
+    Parse(toml::de::Error),
+    UnknownAction(String),
+    UnknownKey(String),
+    UnknownPadInput(String),
+    Override(String),
+    Conflict {
+        key: String,
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+impl std::fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingsError::FileError(e) => write!(f, "File Error: {}!", e),
+            BindingsError::Parse(e) => write!(f, "Parse Error: {}", e),
+            BindingsError::UnknownAction(a) => write!(f, "Unknown action {:?}!", a),
+            BindingsError::UnknownKey(k) => write!(f, "Unknown key {:?}!", k),
+            BindingsError::UnknownPadInput(i) => write!(f, "Unknown controller input {:?}!", i),
+            BindingsError::Override(b) => {
+                write!(f, "Invalid binding {:?}, expected \"action=key\"!", b)
+            }
+            BindingsError::Conflict { key, first, second } => write!(
+                f,
+                "{} is bound to both {} and {}, unbind one with \"\"!",
+                key, first, second
+            ),
+        }
+    }
+}
+
+/// The key and controller inputs bound to each action, in the order of ACTIONS
+pub struct Bindings {
+    keys: Vec<Option<VirtualKeyCode>>,
+    pads: Vec<Vec<PadInput>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let pads = ACTIONS
+            .iter()
+            .map(|(name, _, _)| {
+                PAD_DEFAULTS
+                    .iter()
+                    .find(|(action, _)| action == name)
+                    .map_or(vec![], |(_, inputs)| {
+                        inputs
+                            .iter()
+                            .filter_map(|i| PadInput::from_name(i))
+                            .collect()
+                    })
+            })
+            .collect();
+        Self {
+            keys: ACTIONS.iter().map(|(_, _, key)| Some(*key)).collect(),
+            pads,
+        }
+    }
+}
+
+impl Bindings {
+    /// Starts from the defaults and applies the config file, then the `action=key` and
+    /// `action=input,input` controller overrides.
+    /// Without an explicit file the one in the config directory is used if it exists
+    pub fn load(
+        file: Option<&Path>,
+        overrides: &[String],
+        pad_overrides: &[String],
+    ) -> Result<Self, BindingsError> {
+        let mut bindings = Self::default();
+        let default_file = config_dir().map(|dir| dir.join("bindings.toml"));
+        match file {
+            Some(file) => bindings.apply_file(file)?,
+            None => {
+                if let Some(file) = default_file.filter(|f| f.exists()) {
+                    bindings.apply_file(&file)?;
+                }
+            }
+        }
+        for binding in overrides {
+            match binding.split_once('=') {
+                Some((action, key)) => bindings.bind(action.trim(), key.trim())?,
+                None => return Err(BindingsError::Override(binding.clone())),
+            }
+        }
+        for binding in pad_overrides {
+            match binding.split_once('=') {
+                Some((action, inputs)) => {
+                    let inputs: Vec<&str> = inputs
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|i| !i.is_empty())
+                        .collect();
+                    bindings.bind_pad(action.trim(), &inputs)?
+                }
+                None => return Err(BindingsError::Override(binding.clone())),
+            }
+        }
+        bindings.check()?;
+        Ok(bindings)
+    }
+
+    /// A flat table of action names to winit key names, "" unbinds an action.
+    /// Controller inputs go in a gamepad table, one or a list of them per action:
+    /// ```toml
+    /// up = "Up"
+    /// pause = "Space"
+    ///
+    /// [gamepad]
+    /// a = "South"
+    /// left = ["DPadLeft", "LeftStickX-"]
+    /// ```
+    fn apply_file(&mut self, file: &Path) -> Result<(), BindingsError> {
+        let text = match read_to_string(file) {
+            Ok(t) => t,
+            Err(e) => return Err(BindingsError::FileError(Box::new(e))),
+        };
+        let table: toml::value::Table = toml::from_str(&text).map_err(BindingsError::Parse)?;
+        for (action, key) in table.iter() {
+            match key {
+                toml::Value::String(key) => self.bind(action, key)?,
+                toml::Value::Table(pad) if action == "gamepad" => {
+                    for (action, inputs) in pad.iter() {
+                        let inputs = match inputs {
+                            toml::Value::String(i) if i.is_empty() => vec![],
+                            toml::Value::String(i) => vec![i.as_str()],
+                            toml::Value::Array(list) => list
+                                .iter()
+                                .map(|i| i.as_str().unwrap_or_default())
+                                .collect(),
+                            _ => return Err(BindingsError::UnknownPadInput(inputs.to_string())),
+                        };
+                        self.bind_pad(action, &inputs)?;
+                    }
+                }
+                _ => return Err(BindingsError::UnknownKey(key.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    fn action_index(action: &str) -> Result<usize, BindingsError> {
+        ACTIONS
+            .iter()
+            .position(|(name, _, _)| *name == action)
+            .ok_or_else(|| BindingsError::UnknownAction(action.to_string()))
+    }
+
+    fn bind(&mut self, action: &str, key: &str) -> Result<(), BindingsError> {
+        let index = Self::action_index(action)?;
+        self.keys[index] = if key.is_empty() {
+            None
+        } else {
+            let de: StrDeserializer<ValueError> = key.into_deserializer();
+            Some(
+                VirtualKeyCode::deserialize(de)
+                    .map_err(|_| BindingsError::UnknownKey(key.to_string()))?,
+            )
+        };
+        Ok(())
+    }
+
+    /// Replaces the controller inputs of an action, none unbinds it
+    fn bind_pad(&mut self, action: &str, inputs: &[&str]) -> Result<(), BindingsError> {
+        let index = Self::action_index(action)?;
+        self.pads[index] = inputs
+            .iter()
+            .map(|i| {
+                PadInput::from_name(i).ok_or_else(|| BindingsError::UnknownPadInput(i.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Every key and controller input may only do one thing
+    fn check(&self) -> Result<(), BindingsError> {
+        for (i, key) in self.keys.iter().enumerate() {
+            if let Some(key) = key {
+                if let Some(j) = self.keys[i + 1..].iter().position(|k| k == &Some(*key)) {
+                    return Err(BindingsError::Conflict {
+                        key: format!("{:?}", key),
+                        first: ACTIONS[i].0,
+                        second: ACTIONS[i + 1 + j].0,
+                    });
+                }
+            }
+        }
+        for (i, inputs) in self.pads.iter().enumerate() {
+            for input in inputs {
+                if let Some(j) = self.pads[i + 1..].iter().position(|p| p.contains(input)) {
+                    return Err(BindingsError::Conflict {
+                        key: input.to_string(),
+                        first: ACTIONS[i].0,
+                        second: ACTIONS[i + 1 + j].0,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn key(&self, action: Action) -> Option<VirtualKeyCode> {
+        ACTIONS
+            .iter()
+            .position(|(_, a, _)| *a == action)
+            .and_then(|i| self.keys[i])
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn pad_action(&self, input: PadInput) -> Option<Action> {
+        self.pads
+            .iter()
+            .position(|inputs| inputs.contains(&input))
+            .map(|i| ACTIONS[i].1)
+    }
+
+    pub fn pressed(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.key(action).is_some_and(|k| input.key_pressed(k))
+    }
+
+    pub fn released(&self, input: &WinitInputHelper, action: Action) -> bool {
+        self.key(action).is_some_and(|k| input.key_released(k))
+    }
+}
+
+/// $XDG_CONFIG_HOME/gb or ~/.config/gb, %APPDATA%\gb on Windows and ~/Library/Application Support/gb on macOS
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map(|dir| dir.join("gb"))
+}
@@ -0,0 +1,253 @@
+use pixels::{Pixels, SurfaceTexture};
+use winit::{
+    dpi::LogicalSize,
+    event::WindowEvent,
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowBuilder, WindowId},
+};
+
+use crate::{gb::Gb, palette::Palette};
+
+use self::{
+    oam::{OamView, OAM_VIEW_HEIGHT, OAM_VIEW_WIDTH},
+    tilemap::{TileMapView, TILEMAP_VIEW_HEIGHT, TILEMAP_VIEW_WIDTH},
+};
+
+pub mod oam;
+pub mod tilemap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DebugKind {
+    TileMap,
+    Oam,
+}
+
+impl DebugKind {
+    fn name(&self) -> &'static str {
+        match self {
+            DebugKind::TileMap => "tile maps",
+            DebugKind::Oam => "oam",
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        match self {
+            DebugKind::TileMap => (TILEMAP_VIEW_WIDTH, TILEMAP_VIEW_HEIGHT),
+            DebugKind::Oam => (OAM_VIEW_WIDTH, OAM_VIEW_HEIGHT),
+        }
+    }
+}
+
+/// Debug views shared between the system thread and the window thread.
+/// A view is only captured by the system thread while it is Some, so closed views cost nothing.
+#[derive(Default)]
+pub struct DebugViews {
+    pub tilemap: Option<Box<TileMapView>>,
+    pub oam: Option<Box<OamView>>,
+}
+
+impl DebugViews {
+    pub fn capture(&mut self, gb: &Gb) {
+        if let Some(tilemap) = self.tilemap.as_mut() {
+            tilemap.capture(gb);
+        }
+        if let Some(oam) = self.oam.as_mut() {
+            oam.capture(gb);
+        }
+    }
+
+    pub fn open(&mut self, kind: DebugKind) {
+        match kind {
+            DebugKind::TileMap => self.tilemap = Some(Box::new(TileMapView::new())),
+            DebugKind::Oam => self.oam = Some(Box::new(OamView::new())),
+        }
+    }
+
+    pub fn close(&mut self, kind: DebugKind) {
+        match kind {
+            DebugKind::TileMap => self.tilemap = None,
+            DebugKind::Oam => self.oam = None,
+        }
+    }
+}
+
+/// A secondary window displaying a debug view next to the emulator
+pub struct DebugWindow {
+    kind: DebugKind,
+    window: Window,
+    pixels: Pixels,
+    cursor: Option<(usize, usize)>,
+}
+
+impl DebugWindow {
+    pub fn new<T>(event_loop: &EventLoopWindowTarget<T>, kind: DebugKind, title: &str) -> Self {
+        let (width, height) = kind.size();
+        let window = {
+            let size = LogicalSize::new((width * 2) as f64, (height * 2) as f64);
+            WindowBuilder::new()
+                .with_title(format!("gb | {} | {}", title, kind.name()))
+                .with_inner_size(size)
+                .with_min_inner_size(LogicalSize::new(width as f64, height as f64))
+                .build(event_loop)
+                .unwrap()
+        };
+        let pixels = {
+            let window_size = window.inner_size();
+            let surface_texture =
+                SurfaceTexture::new(window_size.width, window_size.height, &window);
+            Pixels::new(width as u32, height as u32, surface_texture)
+                .expect("Could not create Pixels struct")
+        };
+        Self {
+            kind,
+            window,
+            pixels,
+            cursor: None,
+        }
+    }
+
+    pub fn kind(&self) -> DebugKind {
+        self.kind
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    /// Returns true if the window wants to be closed
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CloseRequested => return true,
+            WindowEvent::Resized(size) => self.pixels.resize_surface(size.width, size.height),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = self
+                    .pixels
+                    .window_pos_to_pixel((position.x as f32, position.y as f32))
+                    .ok();
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            _ => (),
+        }
+        false
+    }
+
+    /// Draws the latest capture and describes whatever is under the cursor in the title.
+    /// `line` is the LCD line under the cursor in the emulator window, if any
+    pub fn update(&mut self, views: &DebugViews, line: Option<usize>, palette: &Palette) {
+        let frame = self.pixels.get_frame_mut();
+        let description = match self.kind {
+            DebugKind::TileMap => views.tilemap.as_ref().map(|view| {
+                view.draw(frame, palette);
+                self.cursor.map(|cursor| view.describe(cursor))
+            }),
+            DebugKind::Oam => views.oam.as_ref().map(|view| {
+                view.draw(frame, line, palette);
+                self.cursor
+                    .and_then(|cursor| view.entry_at(cursor))
+                    .map(|obj_num| view.describe(obj_num, line))
+            }),
+        };
+        match description.flatten() {
+            Some(d) => self.window.set_title(&format!("gb | {}", d)),
+            None => self.window.set_title(&format!("gb | {}", self.kind.name())),
+        }
+        self.window.request_redraw();
+    }
+
+    pub fn render(&self) -> Result<(), pixels::Error> {
+        self.pixels.render()
+    }
+}
+
+/// Draws a one pixel rectangle outline into an RGBA frame, wrapping around the edges like the Game Boy scroll registers
+pub(crate) fn draw_wrapping_rect(
+    frame: &mut [u8],
+    frame_width: usize,
+    view: (usize, usize, usize, usize),
+    rect: (usize, usize, usize, usize),
+    color: [u8; 4],
+) {
+    let (view_x, view_y, view_width, view_height) = view;
+    let (x, y, width, height) = rect;
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut plot = |rel_x: usize, rel_y: usize| {
+        let px = view_x + (x + rel_x) % view_width;
+        let py = view_y + (y + rel_y) % view_height;
+        let i = (py * frame_width + px) * 4;
+        frame[i..i + 4].copy_from_slice(&color);
+    };
+    for rel_x in 0..width {
+        plot(rel_x, 0);
+        plot(rel_x, height - 1);
+    }
+    for rel_y in 0..height {
+        plot(0, rel_y);
+        plot(width - 1, rel_y);
+    }
+}
+
+pub(crate) fn fill_rect(
+    frame: &mut [u8],
+    frame_width: usize,
+    (x, y, width, height): (usize, usize, usize, usize),
+    color: [u8; 4],
+) {
+    for py in y..y + height {
+        for px in x..x + width {
+            let i = (py * frame_width + px) * 4;
+            frame[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+pub(crate) const GLYPH_WIDTH: usize = 4; // Including spacing
+pub(crate) const GLYPH_HEIGHT: usize = 5;
+
+/// 3x5 glyphs, one row per byte, leftmost pixel in bit 2
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0b000; GLYPH_HEIGHT],
+    }
+}
+
+/// Draws text with a tiny built in font, only hex digits and a few symbols are supported
+pub(crate) fn draw_text(
+    frame: &mut [u8],
+    frame_width: usize,
+    (x, y): (usize, usize),
+    text: &str,
+    color: [u8; 4],
+) {
+    for (n, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let i = ((y + row) * frame_width + x + n * GLYPH_WIDTH + col) * 4;
+                    frame[i..i + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    gb::{
+        ppu::{get_attribute, Attribute, LCD_HEIGHT, OAM_OBJECTS},
+        Gb,
+    },
+    palette::Palette,
+};
+
+use super::{draw_text, fill_rect, GLYPH_WIDTH};
+
+// Two columns of 20 entries, each entry is a preview followed by "NN YY XX TT XYP0"
+const ROWS: usize = OAM_OBJECTS / 2;
+const ROW_HEIGHT: usize = 18;
+const TEXT_LEN: usize = 16;
+const COLUMN_WIDTH: usize = 2 + 8 + 4 + TEXT_LEN * GLYPH_WIDTH + 2;
+
+pub const OAM_VIEW_WIDTH: usize = 2 * COLUMN_WIDTH;
+pub const OAM_VIEW_HEIGHT: usize = ROWS * ROW_HEIGHT;
+
+const BACKGROUND_COLOR: [u8; 4] = [0x10, 0x10, 0x10, 0xFF];
+const ROW_COLOR: [u8; 4] = [0x30, 0x30, 0x30, 0xFF];
+const SELECTED_COLOR: [u8; 4] = [0x20, 0x70, 0x20, 0xFF];
+const DROPPED_COLOR: [u8; 4] = [0x90, 0x20, 0x20, 0xFF];
+const TEXT_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+/// Snapshot of OAM, object previews and which objects the OAM scan picked on each line
+pub struct OamView {
+    entries: [(u8, u8, u8, u8); OAM_OBJECTS],
+    previews: [[[Option<u8>; 8]; 16]; OAM_OBJECTS],
+    line_objects: [(u64, u64); LCD_HEIGHT],
+    tall_objects: bool,
+}
+
+impl OamView {
+    pub fn new() -> Self {
+        Self {
+            entries: [(0, 0, 0, 0); OAM_OBJECTS],
+            previews: [[[None; 8]; 16]; OAM_OBJECTS],
+            line_objects: [(0, 0); LCD_HEIGHT],
+            tall_objects: false,
+        }
+    }
+
+    pub fn capture(&mut self, gb: &Gb) {
+        let ppu = gb.ppu();
+        for obj_num in 0..OAM_OBJECTS {
+            self.entries[obj_num] = ppu.oam_entry(obj_num);
+            self.previews[obj_num] = ppu.render_obj(obj_num);
+        }
+        for (ly, objects) in self.line_objects.iter_mut().enumerate() {
+            *objects = ppu.line_objects(ly);
+        }
+        self.tall_objects = ppu.tall_objects();
+    }
+
+    /// Objects selected and dropped on a line, or on any line of the frame if there is no line
+    fn objects(&self, line: Option<usize>) -> (u64, u64) {
+        match line {
+            Some(ly) => self.line_objects[ly],
+            None => self
+                .line_objects
+                .iter()
+                .fold((0, 0), |(s, d), &(ls, ld)| (s | ls, d | ld)),
+        }
+    }
+
+    /// Draws all 40 entries into an OAM_VIEW_WIDTH x OAM_VIEW_HEIGHT RGBA frame.
+    /// Entries selected on the line are green, entries dropped by the 10 per line limit are red
+    pub fn draw(&self, frame: &mut [u8], line: Option<usize>, palette: &Palette) {
+        fill_rect(
+            frame,
+            OAM_VIEW_WIDTH,
+            (0, 0, OAM_VIEW_WIDTH, OAM_VIEW_HEIGHT),
+            BACKGROUND_COLOR,
+        );
+        let (selected, dropped) = self.objects(line);
+        for obj_num in 0..OAM_OBJECTS {
+            let (x0, y0) = (
+                (obj_num / ROWS) * COLUMN_WIDTH,
+                (obj_num % ROWS) * ROW_HEIGHT,
+            );
+            let row_color = if selected & (1 << obj_num) != 0 {
+                SELECTED_COLOR
+            } else if dropped & (1 << obj_num) != 0 {
+                DROPPED_COLOR
+            } else {
+                ROW_COLOR
+            };
+            fill_rect(
+                frame,
+                OAM_VIEW_WIDTH,
+                (x0, y0, COLUMN_WIDTH - 1, ROW_HEIGHT - 1),
+                row_color,
+            );
+
+            // Preview the object the way it is flipped on screen
+            let (y, x, tile_index, attributes) = self.entries[obj_num];
+            let obj_height = if self.tall_objects { 16 } else { 8 };
+            let shades = if get_attribute(attributes, Attribute::PaletteNumber) {
+                &palette.obp1
+            } else {
+                &palette.obp0
+            };
+            for (rel_y, preview_line) in self.previews[obj_num].iter().enumerate().take(obj_height)
+            {
+                for (rel_x, pixel) in preview_line.iter().enumerate() {
+                    if let Some(color) = pixel {
+                        let px = if get_attribute(attributes, Attribute::XFlip) {
+                            7 - rel_x
+                        } else {
+                            rel_x
+                        };
+                        let py = if get_attribute(attributes, Attribute::YFlip) {
+                            obj_height - 1 - rel_y
+                        } else {
+                            rel_y
+                        };
+                        let i = ((y0 + 1 + py) * OAM_VIEW_WIDTH + x0 + 2 + px) * 4;
+                        frame[i..i + 4].copy_from_slice(&shades[*color as usize]);
+                    }
+                }
+            }
+
+            let flag = |attribute, c| {
+                if get_attribute(attributes, attribute) {
+                    c
+                } else {
+                    '-'
+                }
+            };
+            let text = format!(
+                "{:02X} {:02X} {:02X} {:02X} {}{}{}{}",
+                obj_num,
+                y,
+                x,
+                tile_index,
+                flag(Attribute::XFlip, 'X'),
+                flag(Attribute::YFlip, 'Y'),
+                flag(Attribute::BGandWindowOverObj, 'P'),
+                if get_attribute(attributes, Attribute::PaletteNumber) {
+                    '1'
+                } else {
+                    '0'
+                },
+            );
+            draw_text(frame, OAM_VIEW_WIDTH, (x0 + 14, y0 + 6), &text, TEXT_COLOR);
+        }
+    }
+
+    /// OAM entry under a view pixel
+    pub fn entry_at(&self, (x, y): (usize, usize)) -> Option<usize> {
+        let obj_num = (x / COLUMN_WIDTH) * ROWS + y / ROW_HEIGHT;
+        (obj_num < OAM_OBJECTS).then_some(obj_num)
+    }
+
+    pub fn describe(&self, obj_num: usize, line: Option<usize>) -> String {
+        let (y, x, tile_index, attributes) = self.entries[obj_num];
+        let (selected, dropped) = self.objects(line);
+        let scan = if selected & (1 << obj_num) != 0 {
+            "selected"
+        } else if dropped & (1 << obj_num) != 0 {
+            "dropped"
+        } else {
+            "not on line"
+        };
+        let mut flags = vec![];
+        if get_attribute(attributes, Attribute::XFlip) {
+            flags.push("xflip");
+        }
+        if get_attribute(attributes, Attribute::YFlip) {
+            flags.push("yflip");
+        }
+        if get_attribute(attributes, Attribute::BGandWindowOverObj) {
+            flags.push("bg priority");
+        }
+        flags.push(if get_attribute(attributes, Attribute::PaletteNumber) {
+            "obp1"
+        } else {
+            "obp0"
+        });
+        format!(
+            "oam #{} | y: {} x: {} (screen {}, {}) | tile: {:#04x} | attr: {:#04x} {} | {}: {}",
+            obj_num,
+            y,
+            x,
+            y as i16 - 16,
+            x as i16 - 8,
+            tile_index,
+            attributes,
+            flags.join(" "),
+            match line {
+                Some(ly) => format!("line {}", ly),
+                None => "frame".to_string(),
+            },
+            scan,
+        )
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    gb::{
+        ppu::{LCD_HEIGHT, LCD_WIDTH, TILEMAP_0, TILEMAP_1, TILEMAP_TILES, TILEMAP_WIDTH},
+        Gb,
+    },
+    palette::Palette,
+};
+
+use super::draw_wrapping_rect;
+
+// Both tile maps are drawn side by side
+pub const TILEMAP_VIEW_WIDTH: usize = 2 * TILEMAP_WIDTH;
+pub const TILEMAP_VIEW_HEIGHT: usize = TILEMAP_WIDTH;
+
+const TILEMAPS: [u16; 2] = [TILEMAP_0, TILEMAP_1];
+
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+const WINDOW_COLOR: [u8; 4] = [0x00, 0x60, 0xFF, 0xFF];
+
+/// Snapshot of both tile maps and the registers that decide what part of them is on screen
+pub struct TileMapView {
+    maps: [[[u8; TILEMAP_WIDTH]; TILEMAP_WIDTH]; 2],
+    entries: [[[(u8, u8); TILEMAP_TILES]; TILEMAP_TILES]; 2],
+    bg_map: u16,
+    window_map: u16,
+    scroll: (u8, u8),
+    window_position: (u8, u8),
+    window_enabled: bool,
+}
+
+impl TileMapView {
+    pub fn new() -> Self {
+        Self {
+            maps: [[[0; TILEMAP_WIDTH]; TILEMAP_WIDTH]; 2],
+            entries: [[[(0, 0); TILEMAP_TILES]; TILEMAP_TILES]; 2],
+            bg_map: TILEMAP_0,
+            window_map: TILEMAP_0,
+            scroll: (0, 0),
+            window_position: (0, 0),
+            window_enabled: false,
+        }
+    }
+
+    pub fn capture(&mut self, gb: &Gb) {
+        let ppu = gb.ppu();
+        for (map, &base) in TILEMAPS.iter().enumerate() {
+            self.maps[map] = ppu.render_tilemap(base);
+            for (tile_y, line) in self.entries[map].iter_mut().enumerate() {
+                for (tile_x, entry) in line.iter_mut().enumerate() {
+                    *entry = ppu.tilemap_entry(base, tile_x as u8, tile_y as u8);
+                }
+            }
+        }
+        (self.bg_map, self.window_map) = ppu.tilemaps();
+        self.scroll = ppu.scroll();
+        self.window_position = ppu.window_position();
+        self.window_enabled = ppu.window_enabled();
+    }
+
+    /// Draws both maps into a TILEMAP_VIEW_WIDTH x TILEMAP_VIEW_HEIGHT RGBA frame.
+    /// The SCX/SCY viewport is outlined on the background map, the visible part of the window on the window map
+    pub fn draw(&self, frame: &mut [u8], palette: &Palette) {
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let x = i % TILEMAP_VIEW_WIDTH;
+            let y = i / TILEMAP_VIEW_WIDTH;
+            let color = self.maps[x / TILEMAP_WIDTH][y][x % TILEMAP_WIDTH];
+            pixel.copy_from_slice(&palette.bg[color as usize]);
+        }
+
+        let (scx, scy) = self.scroll;
+        draw_wrapping_rect(
+            frame,
+            TILEMAP_VIEW_WIDTH,
+            (Self::map_x(self.bg_map), 0, TILEMAP_WIDTH, TILEMAP_WIDTH),
+            (scx as usize, scy as usize, LCD_WIDTH, LCD_HEIGHT),
+            VIEWPORT_COLOR,
+        );
+
+        if self.window_enabled {
+            // The window always starts drawing from the top left of its map
+            let (wx, wy) = self.window_position;
+            let width = (LCD_WIDTH + 7).saturating_sub(wx as usize).min(LCD_WIDTH);
+            let height = LCD_HEIGHT.saturating_sub(wy as usize);
+            draw_wrapping_rect(
+                frame,
+                TILEMAP_VIEW_WIDTH,
+                (
+                    Self::map_x(self.window_map),
+                    0,
+                    TILEMAP_WIDTH,
+                    TILEMAP_WIDTH,
+                ),
+                (0, 0, width, height),
+                WINDOW_COLOR,
+            );
+        }
+    }
+
+    /// Describes the tile under a view pixel
+    pub fn describe(&self, (x, y): (usize, usize)) -> String {
+        let map = (x / TILEMAP_WIDTH).min(1);
+        let (tile_x, tile_y) = ((x % TILEMAP_WIDTH) / 8, y.min(TILEMAP_WIDTH - 1) / 8);
+        let (tile_index, attributes) = self.entries[map][tile_y][tile_x];
+        let mut users = vec![];
+        if TILEMAPS[map] == self.bg_map {
+            users.push("bg");
+        }
+        if self.window_enabled && TILEMAPS[map] == self.window_map {
+            users.push("win");
+        }
+        format!(
+            "{:#06x} [{}] ({}, {}) | tile: {:#04x} attr: {:#04x} | scx: {} scy: {} wx: {} wy: {}",
+            TILEMAPS[map] as usize + tile_x + tile_y * TILEMAP_TILES,
+            users.join(","),
+            tile_x,
+            tile_y,
+            tile_index,
+            attributes,
+            self.scroll.0,
+            self.scroll.1,
+            self.window_position.0,
+            self.window_position.1,
+        )
+    }
+
+    fn map_x(base: u16) -> usize {
+        if base == TILEMAP_1 {
+            TILEMAP_WIDTH
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::{stdin, stdout, BufRead, Write};
+
+use crate::gb::{
+    cpu::{
+        disasm::Instruction,
+        registers::{Flag, Reg16, Reg8},
+    },
+    watch::Watchpoint,
+    Gb,
+};
+
+const HELP: &str = "\
+Addresses and values are hexadecimal, with or without a $ or 0x prefix, counts are decimal
+  b, break [ADDR | BANK:ADDR]  add a breakpoint, a rom bank one only hits while the bank is mapped in
+                               without an address lists the breakpoints
+  d, delete [N]                delete breakpoint N or all of them
+  watch [RANGE[:COND]]         add a watchpoint like c000-c0ff:w, COND is r, w (default), c for a changed value
+                               or =VALUE, without a range lists the watchpoints
+  unwatch [N]                  delete watchpoint N or all of them
+  c, continue                  run until a breakpoint is hit
+  s, step [COUNT]              run COUNT instructions, stepping into calls
+  n, next                      run one instruction, stepping over calls and rsts
+  f, finish                    run until the current function returns
+  r, reg [REG VALUE]           show the registers or set one (a-l, af, bc, de, hl, sp, pc)
+  flag [FLAG 0|1]              show the flags or set one (z, n, h, c)
+  x [ADDR] [LEN]               dump LEN bytes of memory, defaults to 64 bytes at pc
+  w ADDR VALUE...              write bytes to memory, rom writes switch banks like the cpu's
+  l, list [ADDR] [COUNT]       disassemble COUNT instructions, defaults to around pc
+  h, help                      show this
+  q, quit                      exit the debugger
+An empty line repeats the last command";
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Breakpoint {
+    Pc(u16),
+    /// Only hit while the bank is mapped in
+    Bank(usize, u16),
+}
+
+impl Breakpoint {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            Some((bank, address)) => {
+                let bank = usize::from_str_radix(strip_hex(bank), 16)
+                    .map_err(|_| format!("invalid bank: {}", bank))?;
+                let address = parse_u16(address)?;
+                if address > 0x7FFF {
+                    return Err("rom banks are mapped in 0000-7fff".to_string());
+                }
+                Ok(Breakpoint::Bank(bank, address))
+            }
+            None => Ok(Breakpoint::Pc(parse_u16(s)?)),
+        }
+    }
+
+    fn hit(&self, gb: &Gb, pc: u16) -> bool {
+        match *self {
+            Breakpoint::Pc(address) => pc == address,
+            Breakpoint::Bank(bank, address) => pc == address && gb.rom_bank(pc) == bank,
+        }
+    }
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Pc(address) => write!(f, "{:04x}", address),
+            Breakpoint::Bank(bank, address) => write!(f, "{:02x}:{:04x}", bank, address),
+        }
+    }
+}
+
+fn strip_hex(s: &str) -> &str {
+    s.strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s)
+}
+
+fn parse_u16(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(strip_hex(s), 16).map_err(|_| format!("invalid address: {}", s))
+}
+
+fn parse_u8(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(strip_hex(s), 16).map_err(|_| format!("invalid byte: {}", s))
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("invalid count: {}", s))
+}
+
+struct Debugger {
+    gb: Gb,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    /// Bank and address of pc the way disasm prints them
+    fn location(&self, address: u16) -> String {
+        match address {
+            0x0000..=0x7FFF => format!("{:02x}:{:04x}", self.gb.rom_bank(address), address),
+            _ => format!("--:{:04x}", address),
+        }
+    }
+
+    fn show_pc(&self) {
+        let pc = self.gb.registers().pc;
+        println!("{}  {}", self.location(pc), self.gb.disasm(pc));
+    }
+
+    /// Steps until done returns true for the instruction that just ran, a breakpoint is hit or the cpu locks up
+    fn run(&mut self, mut done: impl FnMut(&Gb, &Instruction) -> bool) {
+        loop {
+            let instr = self.gb.disasm(self.gb.registers().pc);
+            self.gb.step();
+            if let Some((pc, opcode)) = self.gb.cpu_locked() {
+                println!(
+                    "CPU locked up on illegal opcode {:#04x} at {:#06x}",
+                    opcode, pc
+                );
+                break;
+            }
+            let pc = self.gb.registers().pc;
+            if let Some((pc, access)) = self.gb.take_watch_hit() {
+                println!("Watchpoint at {}: {}", self.location(pc), access);
+                break;
+            }
+            if let Some(i) = self.breakpoints.iter().position(|b| b.hit(&self.gb, pc)) {
+                println!("Breakpoint {} at {}", i, self.breakpoints[i]);
+                break;
+            }
+            if done(&self.gb, &instr) {
+                break;
+            }
+        }
+        self.show_pc();
+    }
+
+    fn show_registers(&self) {
+        let r = self.gb.registers();
+        println!("pc: {:#06x}\t{}", r.pc, self.gb.print());
+        self.show_flags();
+    }
+
+    fn show_flags(&self) {
+        let r = self.gb.registers();
+        let flag = |flag, name| if r.get_flag(flag) { name } else { '-' };
+        println!(
+            "flags: {}{}{}{}",
+            flag(Flag::Z, 'z'),
+            flag(Flag::N, 'n'),
+            flag(Flag::H, 'h'),
+            flag(Flag::C, 'c')
+        );
+    }
+
+    fn set_register(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let r = self.gb.registers_mut();
+        let reg8 = match name {
+            "a" => Some(Reg8::A),
+            "f" => Some(Reg8::F),
+            "b" => Some(Reg8::B),
+            "c" => Some(Reg8::C),
+            "d" => Some(Reg8::D),
+            "e" => Some(Reg8::E),
+            "h" => Some(Reg8::H),
+            "l" => Some(Reg8::L),
+            _ => None,
+        };
+        if let Some(reg) = reg8 {
+            r.set_8(reg, parse_u8(value)?);
+            return Ok(());
+        }
+        let value = parse_u16(value)?;
+        match name {
+            "af" => r.set_16(Reg16::AF, value),
+            "bc" => r.set_16(Reg16::BC, value),
+            "de" => r.set_16(Reg16::DE, value),
+            "hl" => r.set_16(Reg16::HL, value),
+            "sp" => r.set_16(Reg16::SP, value),
+            "pc" => r.pc = value,
+            _ => return Err(format!("unknown register: {}", name)),
+        }
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let flag = match name {
+            "z" => Flag::Z,
+            "n" => Flag::N,
+            "h" => Flag::H,
+            "c" => Flag::C,
+            _ => return Err(format!("unknown flag: {}", name)),
+        };
+        let value = match value {
+            "0" => false,
+            "1" => true,
+            _ => return Err(format!("flags are 0 or 1, not {}", value)),
+        };
+        self.gb.registers_mut().set_flag(flag, value);
+        Ok(())
+    }
+
+    fn dump(&self, address: u16, len: usize) {
+        for line in (0..len).step_by(16) {
+            let start = address.wrapping_add(line as u16);
+            let bytes: Vec<u8> = (0..16.min(len - line))
+                .map(|i| self.gb.read(start.wrapping_add(i as u16)))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            println!("{:04x}  {:<47}  {}", start, hex.join(" "), ascii);
+        }
+    }
+
+    /// Start of up to count instructions leading up to address. Decoding backwards is ambiguous, this picks
+    /// the furthest start that lines up with address
+    fn back(&self, address: u16, count: usize) -> u16 {
+        for distance in (1..=count as u16 * 3).rev() {
+            let start = address.wrapping_sub(distance);
+            let (mut offset, mut n) = (0, 0);
+            while offset < distance {
+                offset += self.gb.disasm(start.wrapping_add(offset)).len;
+                n += 1;
+            }
+            if offset == distance && n <= count {
+                return start;
+            }
+        }
+        address
+    }
+
+    fn list(&self, start: u16, count: usize) {
+        let pc = self.gb.registers().pc;
+        let mut address = start;
+        for _ in 0..count {
+            let instr = self.gb.disasm(address);
+            let bytes: Vec<String> = (0..instr.len)
+                .map(|i| format!("{:02x}", self.gb.read(address.wrapping_add(i))))
+                .collect();
+            let breakpoint = self.breakpoints.iter().any(|b| b.hit(&self.gb, address));
+            println!(
+                "{}{} {}  {:<9} {}",
+                if address == pc { '>' } else { ' ' },
+                if breakpoint { '*' } else { ' ' },
+                self.location(address),
+                bytes.join(" "),
+                instr
+            );
+            address = address.wrapping_add(instr.len);
+        }
+    }
+
+    /// Runs a command, returns false to quit
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = args.split_first() else {
+            return Ok(true);
+        };
+        match (command, args) {
+            ("b" | "break", []) => {
+                for (i, b) in self.breakpoints.iter().enumerate() {
+                    println!("{}: {}", i, b);
+                }
+            }
+            ("b" | "break", [address]) => {
+                let breakpoint = Breakpoint::parse(address)?;
+                if !self.breakpoints.contains(&breakpoint) {
+                    self.breakpoints.push(breakpoint);
+                }
+                println!("Breakpoint at {}", breakpoint);
+            }
+            ("d" | "delete", []) => self.breakpoints.clear(),
+            ("d" | "delete", [n]) => {
+                let n = parse_count(n)?;
+                if n >= self.breakpoints.len() {
+                    return Err(format!("no breakpoint {}", n));
+                }
+                self.breakpoints.remove(n);
+            }
+            ("watch", []) => {
+                for (i, w) in self.gb.watchpoints().iter().enumerate() {
+                    println!("{}: {}", i, w);
+                }
+            }
+            ("watch", [watch]) => {
+                let watchpoint = Watchpoint::parse(watch)?;
+                self.gb.watchpoints_mut().push(watchpoint);
+                println!("Watchpoint on {}", watchpoint);
+            }
+            ("unwatch", []) => self.gb.watchpoints_mut().clear(),
+            ("unwatch", [n]) => {
+                let n = parse_count(n)?;
+                if n >= self.gb.watchpoints().len() {
+                    return Err(format!("no watchpoint {}", n));
+                }
+                self.gb.watchpoints_mut().remove(n);
+            }
+            ("c" | "continue", []) => self.run(|_, _| false),
+            ("s" | "step", [] | [_]) => {
+                let mut count = args.first().map_or(Ok(1), |c| parse_count(c))?;
+                self.run(|_, _| {
+                    count = count.saturating_sub(1);
+                    count == 0
+                });
+            }
+            ("n" | "next", []) => {
+                let r = self.gb.registers();
+                let (pc, sp) = (r.pc, r.get_16(Reg16::SP));
+                let instr = self.gb.disasm(pc);
+                if let "call" | "rst" = instr.mnemonic {
+                    // The sp check keeps recursive calls from stopping early
+                    let ret = pc.wrapping_add(instr.len);
+                    self.run(|gb, _| {
+                        let r = gb.registers();
+                        r.pc == ret && r.get_16(Reg16::SP) >= sp
+                    });
+                } else {
+                    self.run(|_, _| true);
+                }
+            }
+            ("f" | "finish", []) => {
+                // Interrupt handlers return with sp back where it was, a ret out of this function pops above it
+                let sp = self.gb.registers().get_16(Reg16::SP);
+                self.run(|gb, instr| {
+                    matches!(instr.mnemonic, "ret" | "reti")
+                        && gb.registers().get_16(Reg16::SP) > sp
+                });
+            }
+            ("r" | "reg", []) => self.show_registers(),
+            ("r" | "reg", [name, value]) => {
+                self.set_register(name, value)?;
+                self.show_registers();
+            }
+            ("flag", []) => self.show_flags(),
+            ("flag", [name, value]) => {
+                self.set_flag(name, value)?;
+                self.show_flags();
+            }
+            ("x", [] | [_] | [_, _]) => {
+                let address = match args.first() {
+                    Some(a) => parse_u16(a)?,
+                    None => self.gb.registers().pc,
+                };
+                let len = args.get(1).map_or(Ok(0x40), |l| parse_count(l))?;
+                self.dump(address, len);
+            }
+            ("w", [address, values @ ..]) if !values.is_empty() => {
+                let address = parse_u16(address)?;
+                let values = values
+                    .iter()
+                    .map(|v| parse_u8(v))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (i, value) in values.into_iter().enumerate() {
+                    self.gb.write(address.wrapping_add(i as u16), value);
+                }
+            }
+            ("l" | "list", [] | [_] | [_, _]) => {
+                let count = args.get(1).map_or(Ok(10), |c| parse_count(c))?;
+                let start = match args.first() {
+                    Some(a) => parse_u16(a)?,
+                    None => self.back(self.gb.registers().pc, 4),
+                };
+                self.list(start, count);
+            }
+            ("h" | "help", []) => println!("{}", HELP),
+            ("q" | "quit", []) => return Ok(false),
+            _ => {
+                return Err(format!(
+                    "unknown command or arguments: {}, try help",
+                    line.trim()
+                ))
+            }
+        }
+        Ok(true)
+    }
+}
+
+pub fn run_debugger(gb: Gb) {
+    let mut debugger = Debugger {
+        gb,
+        breakpoints: Vec::new(),
+    };
+    let mut last = String::new();
+    debugger.show_pc();
+    let mut lines = stdin().lock().lines();
+    loop {
+        print!("(gb) ");
+        stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        // Repeating is handy for stepping, gdb does the same
+        if !line.trim().is_empty() {
+            last = line;
+        }
+        match debugger.command(&last) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
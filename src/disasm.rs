@@ -0,0 +1,57 @@
+use crate::gb::cpu::disasm::decode;
+
+pub const BANK_SIZE: usize = 0x4000;
+
+/// A single bank like 1 or an inclusive range like 0-3
+pub fn parse_banks(s: &str) -> Result<(usize, usize), String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|_| "expected a bank like 1 or a range like 0-3".to_string())
+    };
+    let (first, last) = match s.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if first > last {
+        return Err("the first bank has to come before the last one".to_string());
+    }
+    Ok((first, last))
+}
+
+pub fn bank_count(rom: &[u8]) -> usize {
+    rom.len().div_ceil(BANK_SIZE)
+}
+
+/// Prints the banks at the addresses they are mapped to, bank 0 at 0x0000 and the rest at 0x4000
+pub fn run_disasm(rom: &[u8], first: usize, last: usize) {
+    for bank in first..=last {
+        let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+        let start = bank * BANK_SIZE;
+        let bytes = &rom[start..(start + BANK_SIZE).min(rom.len())];
+        // Instructions running past the end of the bank read open bus
+        let read = |address: u16| {
+            bytes
+                .get(address.wrapping_sub(base) as usize)
+                .copied()
+                .unwrap_or(0xFF)
+        };
+        println!("; bank {}", bank);
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let address = base + offset as u16;
+            let instr = decode(address, read);
+            let raw: Vec<String> = (0..instr.len)
+                .map(|i| format!("{:02x}", read(address.wrapping_add(i))))
+                .collect();
+            println!(
+                "{:02x}:{:04x}  {:<9} {}",
+                bank,
+                address,
+                raw.join(" "),
+                instr
+            );
+            offset += instr.len as usize;
+        }
+    }
+}
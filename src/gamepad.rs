@@ -0,0 +1,114 @@
+use std::error::Error;
+
+use gilrs::{EventType, GamepadId, Gilrs};
+
+use crate::bindings::{Action, Bindings, PadInput};
+
+/// Controllers through gilrs, which also reports them being plugged in and out
+pub struct Gamepads {
+    gilrs: Gilrs,
+    /// How far a stick has to be pushed to count as pressed, from 0 to 1
+    dead_zone: f32,
+    /// Inputs held on each controller and what they do, released when it is unplugged
+    held: Vec<(GamepadId, PadInput, Action)>,
+}
+
+impl Gamepads {
+    pub fn new(dead_zone: f32) -> Result<Self, Box<dyn Error>> {
+        let gilrs = Gilrs::new().map_err(Box::new)?;
+        for (_, pad) in gilrs.gamepads() {
+            println!("Controller connected: {}", pad.name());
+        }
+        Ok(Self {
+            gilrs,
+            dead_zone,
+            held: vec![],
+        })
+    }
+
+    /// Actions pressed (true) or released (false) since the last call
+    pub fn poll(&mut self, bindings: &Bindings) -> Vec<(Action, bool)> {
+        let mut actions = vec![];
+        while let Some(event) = self.gilrs.next_event() {
+            let id = event.id;
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(input) = PadInput::from_name(&format!("{:?}", button)) {
+                        self.press(id, input, bindings, &mut actions);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(input) = PadInput::from_name(&format!("{:?}", button)) {
+                        self.release(id, input, &mut actions);
+                    }
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    let name = format!("{:?}", axis);
+                    for (sign, positive) in [("+", true), ("-", false)] {
+                        let input = match PadInput::from_name(&(name.clone() + sign)) {
+                            Some(i) => i,
+                            None => break,
+                        };
+                        let pushed = if positive {
+                            value > self.dead_zone
+                        } else {
+                            value < -self.dead_zone
+                        };
+                        if pushed {
+                            self.press(id, input, bindings, &mut actions);
+                        } else {
+                            self.release(id, input, &mut actions);
+                        }
+                    }
+                }
+                EventType::Connected => {
+                    println!("Controller connected: {}", self.gilrs.gamepad(id).name());
+                }
+                EventType::Disconnected => {
+                    println!("Controller disconnected: {}", self.gilrs.gamepad(id).name());
+                    // Nothing stays stuck down when a controller is pulled mid press
+                    while let Some(i) = self.held.iter().position(|(h, _, _)| *h == id) {
+                        let (_, _, action) = self.held.remove(i);
+                        actions.push((action, false));
+                    }
+                }
+                _ => (),
+            }
+        }
+        actions
+    }
+
+    fn press(
+        &mut self,
+        id: GamepadId,
+        input: PadInput,
+        bindings: &Bindings,
+        actions: &mut Vec<(Action, bool)>,
+    ) {
+        if self.held.iter().any(|(h, i, _)| *h == id && *i == input) {
+            return;
+        }
+        if let Some(action) = bindings.pad_action(input) {
+            self.held.push((id, input, action));
+            actions.push((action, true));
+        }
+    }
+
+    fn release(&mut self, id: GamepadId, input: PadInput, actions: &mut Vec<(Action, bool)>) {
+        if let Some(i) = self
+            .held
+            .iter()
+            .position(|(h, i, _)| *h == id && *i == input)
+        {
+            let (_, _, action) = self.held.remove(i);
+            actions.push((action, false));
+        }
+    }
+}
+
+pub fn parse_dead_zone(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(d) if (0.0..1.0).contains(&d) => Ok(d),
+        _ => Err("expected a number from 0 to 1, e.g. 0.5".to_string()),
+    }
+}
@@ -38,6 +38,8 @@ pub const NR52: u16 = 0xFF26; // sound on/off
 pub const WAVE_BEGIN: u16 = 0xFF30; // Samples 0 and 1
 pub const WAVE_END: u16 = 0xFF3F; // Samples 30 and 31
 
+use super::state::{StateError, StateReader, StateWriter};
+
 pub struct Apu {
     m_squares: [[u8; 5]; 2],
     m_wave: [u8; 5],
@@ -69,6 +71,27 @@ impl Apu {
         self.m_control = [0x77, 0xF3, 0xF1];
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for square in self.m_squares.iter() {
+            w.bytes(square);
+        }
+        w.bytes(&self.m_wave);
+        w.bytes(&self.m_noise);
+        w.bytes(&self.m_control);
+        w.bytes(&self.m_wave_table);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for square in self.m_squares.iter_mut() {
+            r.bytes_into(square, "square channel")?;
+        }
+        r.bytes_into(&mut self.m_wave, "wave channel")?;
+        r.bytes_into(&mut self.m_noise, "noise channel")?;
+        r.bytes_into(&mut self.m_control, "sound control")?;
+        r.bytes_into(&mut self.m_wave_table, "wave table")?;
+        Ok(())
+    }
+
     pub fn b(&self, address: u16) -> u8 {
         match address {
             NR10..=NR24 => {
@@ -0,0 +1,28 @@
+use super::mmu::Interrupt;
+
+/// Everything the cpu sees of the rest of the system, Mmu for the Game Boy itself
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    /// Reads without it counting as an access by the cpu, for reporting what it is about to run
+    fn peek(&self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, value: u8);
+
+    /// Runs everything but the cpu for the given T-cycles
+    fn tick(&mut self, cycles: u32);
+
+    fn has_pending_interrupts(&self) -> bool;
+
+    /// The pending interrupt with the highest priority, only called when there is one
+    fn next_interrupt(&self) -> Interrupt;
+
+    /// Clears the request of an interrupt that is being handled
+    fn disable_interrupt(&mut self, i: Interrupt);
+
+    /// Whether a button is pressed on a selected joypad line, which wakes the cpu up from STOP
+    fn joypad_pressed(&self) -> bool;
+
+    /// The cpu entered STOP, which resets DIV
+    fn stop(&mut self);
+}
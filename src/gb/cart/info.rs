@@ -51,6 +51,7 @@ pub struct CartridgeInfo {
     pub region: Region,
     pub version: u8,
     pub header_checksum: u8,
+    pub global_checksum: u16,
     pub old_licensee_code: OldLicenseeCode,
     pub new_licensee_code: Option<NewLicenseeCode>,
 }
@@ -119,6 +120,10 @@ impl CartridgeInfo {
             };
             let version = bytes[0x014C];
             let header_checksum = bytes[0x014D];
+            let global_checksum = match bytes.get(0x014E..=0x014F) {
+                Some(&[hi, lo]) => (hi as u16) << 8 | lo as u16,
+                _ => 0,
+            };
 
             let mapper = match cart_type {
                 CartType::Rom => MapperType::Rom,
@@ -205,6 +210,7 @@ impl CartridgeInfo {
                 region,
                 version,
                 header_checksum,
+                global_checksum,
                 old_licensee_code,
                 new_licensee_code,
             })
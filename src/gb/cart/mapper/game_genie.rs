@@ -2,7 +2,10 @@ use std::cell::RefCell;
 
 use num_enum::IntoPrimitive;
 
-use crate::gb::cart::info::CartridgeInfo;
+use crate::gb::{
+    cart::info::CartridgeInfo,
+    state::{StateError, StateReader, StateWriter},
+};
 
 use super::{Mapper, RamLoadError, RamSaveError};
 
@@ -150,6 +153,24 @@ impl Mapper for GameGenie {
         self.mapper.reset_save()
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.state.borrow().m_control_0);
+        w.u8(self.m_code_enable);
+        for code in self.m_code.iter() {
+            w.bytes(code);
+        }
+        self.mapper.save_state(w);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.state.borrow_mut().m_control_0 = r.u8()?;
+        self.m_code_enable = r.u8()?;
+        for code in self.m_code.iter_mut() {
+            r.bytes_into(code, "game genie code")?;
+        }
+        self.update_codes();
+        self.mapper.load_state(r)
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         let mut state = self.state.borrow_mut();
 
@@ -175,6 +196,13 @@ impl Mapper for GameGenie {
             self.mapper.rom_b(address)
         }
     }
+    fn rom_bank(&self, address: u16) -> usize {
+        if self.state.borrow().get_flag(Control0Flag::CartPassthrough) {
+            self.mapper.rom_bank(address)
+        } else {
+            0
+        }
+    }
     fn rom_wb(&mut self, address: u16, value: u8) {
         if !self.state.borrow().get_flag(Control0Flag::CartPassthrough) {
             match address {
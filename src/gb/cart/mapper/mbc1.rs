@@ -1,6 +1,9 @@
 use num_enum::UnsafeFromPrimitive;
 
-use crate::gb::cart::info::CartridgeInfo;
+use crate::gb::{
+    cart::info::CartridgeInfo,
+    state::{StateError, StateReader, StateWriter},
+};
 
 use super::{Mapper, RamLoadError, RamSaveError, RAM_BANK_SIZE, ROM_BANK_SIZE};
 
@@ -128,6 +131,21 @@ impl Mapper for Mbc1 {
         self.ram.fill(0);
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        w.bool(self.ram_enable);
+        w.u8(self.bank);
+        w.u8(self.mode as u8);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.ram_enable = r.bool()?;
+        self.bank = r.u8()?;
+        self.mode = unsafe { BankingMode::from_unchecked(r.u8()? & 0b1) };
+        self.recalculate_offsets();
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x3FFF => self.rom[address as usize + self.rom_lo_offset],
@@ -135,6 +153,12 @@ impl Mapper for Mbc1 {
             _ => unreachable!(),
         }
     }
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => self.rom_lo_offset / ROM_BANK_SIZE,
+            _ => self.rom_hi_offset / ROM_BANK_SIZE,
+        }
+    }
     fn rom_wb(&mut self, address: u16, value: u8) {
         match address {
             0x0000..=0x1FFF if self.has_ram => self.ram_enable = (value & 0x0F) == 0x0A,
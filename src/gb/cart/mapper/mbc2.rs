@@ -1,4 +1,7 @@
-use crate::gb::cart::info::CartridgeInfo;
+use crate::gb::{
+    cart::info::CartridgeInfo,
+    state::{StateError, StateReader, StateWriter},
+};
 
 use super::{Mapper, RamLoadError, RamSaveError, ROM_BANK_SIZE};
 
@@ -80,6 +83,19 @@ impl Mapper for Mbc2 {
         self.ram.fill(0);
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        w.bool(self.ram_enable);
+        w.u8(self.bank as u8);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.ram_enable = r.bool()?;
+        self.bank = r.u8()? as usize;
+        self.recalculate_offsets();
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x3FFF => self.rom[address as usize],
@@ -87,6 +103,12 @@ impl Mapper for Mbc2 {
             _ => unreachable!(),
         }
     }
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_offset / ROM_BANK_SIZE,
+        }
+    }
     fn rom_wb(&mut self, address: u16, value: u8) {
         if let 0x0000..=0x3FFF = address {
             // Register selection determined by address bit 8
@@ -1,4 +1,7 @@
-use crate::gb::cart::info::CartridgeInfo;
+use crate::gb::{
+    cart::info::CartridgeInfo,
+    state::{StateError, StateReader, StateWriter},
+};
 
 use super::{Mapper, RamLoadError, RamSaveError, RAM_BANK_SIZE, ROM_BANK_SIZE};
 
@@ -115,6 +118,39 @@ impl Mapper for Mbc3 {
         self.ram.fill(0);
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        w.bool(self.ram_rtc_enable);
+        w.u8(self.rom_bank as u8);
+        w.u8(match self.mode {
+            RamMode::None => 0xFF,
+            RamMode::Bank(bank) => bank as u8,
+            RamMode::Seconds => RTC_S,
+            RamMode::Minutes => RTC_M,
+            RamMode::Hours => RTC_H,
+            RamMode::DayLow => RTC_DL,
+            RamMode::DayHigh => RTC_DH,
+        });
+        w.bool(self.latch_read_0);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.ram_rtc_enable = r.bool()?;
+        self.rom_bank = r.u8()? as usize;
+        self.mode = match r.u8()? {
+            bank @ 0x00..=0x03 => RamMode::Bank(bank as usize),
+            RTC_S => RamMode::Seconds,
+            RTC_M => RamMode::Minutes,
+            RTC_H => RamMode::Hours,
+            RTC_DL => RamMode::DayLow,
+            RTC_DH => RamMode::DayHigh,
+            _ => RamMode::None,
+        };
+        self.latch_read_0 = r.bool()?;
+        self.recalculate_offsets();
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x3FFF => self.rom[address as usize],
@@ -122,6 +158,12 @@ impl Mapper for Mbc3 {
             _ => unreachable!(),
         }
     }
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_offset / ROM_BANK_SIZE,
+        }
+    }
     fn rom_wb(&mut self, address: u16, value: u8) {
         match address {
             0x0000..=0x1FFF => {
@@ -1,4 +1,7 @@
-use crate::gb::cart::info::CartridgeInfo;
+use crate::gb::{
+    cart::info::CartridgeInfo,
+    state::{StateError, StateReader, StateWriter},
+};
 
 use super::{Mapper, RamLoadError, RamSaveError, RAM_BANK_SIZE, ROM_BANK_SIZE};
 
@@ -98,6 +101,21 @@ impl Mapper for Mbc5 {
         self.ram.fill(0);
     }
 
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.ram);
+        w.bool(self.ram_enable);
+        w.u16(self.rom_bank);
+        w.u8(self.ram_bank);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.ram_enable = r.bool()?;
+        self.rom_bank = r.u16()? & 0x1FF;
+        self.ram_bank = r.u8()? & 0x0F;
+        self.recalculate_offsets();
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x3FFF => self.rom[address as usize],
@@ -105,6 +123,12 @@ impl Mapper for Mbc5 {
             _ => unreachable!(),
         }
     }
+    fn rom_bank(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_offset / ROM_BANK_SIZE,
+        }
+    }
     fn rom_wb(&mut self, address: u16, value: u8) {
         match address {
             0x0000..=0x1FFF if self.has_ram => self.ram_enable = (value & 0x0F) == 0x0A,
@@ -4,6 +4,7 @@ use self::{
 };
 
 use super::info::CartridgeInfo;
+use crate::gb::state::{StateError, StateReader, StateWriter};
 
 mod game_genie;
 mod mbc1;
@@ -39,11 +40,20 @@ pub trait Mapper: Send {
     fn save_save(&mut self) -> Result<Vec<u8>, RamSaveError>;
     fn reset_save(&mut self);
 
+    // Bank registers and cartridge RAM for save states, the ROM itself is not saved
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+
     fn rom_b(&self, address: u16) -> u8;
     fn rom_wb(&mut self, address: u16, value: u8);
     fn ram_b(&self, address: u16) -> u8;
     fn ram_wb(&mut self, address: u16, value: u8);
 
+    /// Rom bank mapped at an address in 0x0000..=0x7FFF
+    fn rom_bank(&self, address: u16) -> usize {
+        (address >= 0x4000) as usize
+    }
+
     fn calculate_header_checksum(&self) -> u8 {
         let mut checksum: u8 = 0;
         for index in 0x0134..0x014D {
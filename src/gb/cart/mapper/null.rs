@@ -1,3 +1,5 @@
+use crate::gb::state::{StateError, StateReader, StateWriter};
+
 use super::{Mapper, RamLoadError, RamSaveError};
 
 pub struct NullMapper;
@@ -23,6 +25,11 @@ impl Mapper for NullMapper {
     }
     fn reset_save(&mut self) {}
 
+    fn save_state(&self, w: &mut StateWriter) {}
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         0xFF
     }
@@ -1,3 +1,5 @@
+use crate::gb::state::{StateError, StateReader, StateWriter};
+
 use super::{Mapper, RamLoadError, RamSaveError};
 
 pub struct Rom {
@@ -25,6 +27,11 @@ impl Mapper for Rom {
     }
     fn reset_save(&mut self) {}
 
+    fn save_state(&self, w: &mut StateWriter) {}
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+
     fn rom_b(&self, address: u16) -> u8 {
         self.rom[address as usize]
     }
@@ -5,6 +5,8 @@ use self::{
     mapper::Mapper,
 };
 
+use super::state::{StateError, StateReader, StateWriter};
+
 pub mod info;
 mod mapper;
 
@@ -67,6 +69,14 @@ impl Cartridge {
         self.mapper.reset()
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.mapper.save_state(w)
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mapper.load_state(r)
+    }
+
     pub fn header_checksum(&self) -> bool {
         self.mapper.calculate_header_checksum() == self.info.header_checksum
     }
@@ -83,6 +93,10 @@ impl Cartridge {
         self.mapper.rom_wb(address, value)
     }
 
+    pub fn rom_bank(&self, address: u16) -> usize {
+        self.mapper.rom_bank(address)
+    }
+
     pub fn ram_b(&self, address: u16) -> u8 {
         self.mapper.ram_b(address)
     }
@@ -0,0 +1,222 @@
+use std::fmt::Display;
+
+use crate::gb::{
+    apu::{
+        NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33, NR34, NR41,
+        NR42, NR43, NR44, NR50, NR51, NR52,
+    },
+    joypad::P1,
+    mmu::{BANK, DMA, HDMA1, HDMA2, HDMA3, HDMA4, HDMA5, INTE, INTF, KEY0, KEY1, RP, SVBK},
+    ppu::{BCPD, BCPS, BGP, LCDC, LY, LYC, OBP0, OBP1, OCPD, OCPS, SCX, SCY, STAT, VBK, WX, WY},
+    serial::{SB, SC},
+    timer::{DIV, TAC, TIMA, TMA},
+};
+
+const REG8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const REG16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const REG16_STACK: [&str; 4] = ["bc", "de", "hl", "af"];
+const COND: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operands: String,
+    /// Bytes including the opcode and the CB prefix
+    pub len: u16,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+/// Name of an I/O register
+pub fn io_name(address: u16) -> Option<&'static str> {
+    Some(match address {
+        P1 => "P1",
+        SB => "SB",
+        SC => "SC",
+        DIV => "DIV",
+        TIMA => "TIMA",
+        TMA => "TMA",
+        TAC => "TAC",
+        INTF => "IF",
+        NR10 => "NR10",
+        NR11 => "NR11",
+        NR12 => "NR12",
+        NR13 => "NR13",
+        NR14 => "NR14",
+        NR21 => "NR21",
+        NR22 => "NR22",
+        NR23 => "NR23",
+        NR24 => "NR24",
+        NR30 => "NR30",
+        NR31 => "NR31",
+        NR32 => "NR32",
+        NR33 => "NR33",
+        NR34 => "NR34",
+        NR41 => "NR41",
+        NR42 => "NR42",
+        NR43 => "NR43",
+        NR44 => "NR44",
+        NR50 => "NR50",
+        NR51 => "NR51",
+        NR52 => "NR52",
+        LCDC => "LCDC",
+        STAT => "STAT",
+        SCY => "SCY",
+        SCX => "SCX",
+        LY => "LY",
+        LYC => "LYC",
+        DMA => "DMA",
+        BGP => "BGP",
+        OBP0 => "OBP0",
+        OBP1 => "OBP1",
+        WY => "WY",
+        WX => "WX",
+        KEY0 => "KEY0",
+        KEY1 => "KEY1",
+        VBK => "VBK",
+        BANK => "BANK",
+        HDMA1 => "HDMA1",
+        HDMA2 => "HDMA2",
+        HDMA3 => "HDMA3",
+        HDMA4 => "HDMA4",
+        HDMA5 => "HDMA5",
+        RP => "RP",
+        BCPS => "BCPS",
+        BCPD => "BCPD",
+        OCPS => "OCPS",
+        OCPD => "OCPD",
+        SVBK => "SVBK",
+        INTE => "IE",
+        _ => return None,
+    })
+}
+
+fn address(address: u16) -> String {
+    match io_name(address) {
+        Some(name) => name.to_string(),
+        None => format!("${:04x}", address),
+    }
+}
+
+/// Decodes the instruction at address, read is called for each of its bytes
+pub fn decode(address: u16, read: impl Fn(u16) -> u8) -> Instruction {
+    let op = read(address);
+    let n8 = read(address.wrapping_add(1));
+    let n16 = (read(address.wrapping_add(2)) as u16) << 8 | n8 as u16;
+    // jr targets are relative to the end of the instruction
+    let jr_target = address.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+    let (x, y, z) = (op >> 6, (op >> 3 & 7) as usize, op & 7);
+    let (p, q) = (y >> 1, y & 1);
+    let r = |i: u8| REG8[i as usize];
+
+    let (mnemonic, operands, len) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("nop", String::new(), 1),
+            1 => ("ld", format!("[{}], sp", self::address(n16)), 3),
+            2 => ("stop", String::new(), 2),
+            3 => ("jr", format!("${:04x}", jr_target), 2),
+            _ => ("jr", format!("{}, ${:04x}", COND[y - 4], jr_target), 2),
+        },
+        (0, 1) if q == 0 => ("ld", format!("{}, ${:04x}", REG16[p], n16), 3),
+        (0, 1) => ("add", format!("hl, {}", REG16[p]), 1),
+        (0, 2) => {
+            let pointer = ["[bc]", "[de]", "[hl+]", "[hl-]"][p];
+            if q == 0 {
+                ("ld", format!("{}, a", pointer), 1)
+            } else {
+                ("ld", format!("a, {}", pointer), 1)
+            }
+        }
+        (0, 3) => (["inc", "dec"][q], REG16[p].to_string(), 1),
+        (0, 4) => ("inc", REG8[y].to_string(), 1),
+        (0, 5) => ("dec", REG8[y].to_string(), 1),
+        (0, 6) => ("ld", format!("{}, ${:02x}", REG8[y], n8), 2),
+        (0, 7) => (
+            ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"][y],
+            String::new(),
+            1,
+        ),
+        (1, 6) if y == 6 => ("halt", String::new(), 1),
+        (1, _) => ("ld", format!("{}, {}", REG8[y], r(z)), 1),
+        (2, _) => alu(y, r(z).to_string(), 1),
+        (3, 0) => match y {
+            0..=3 => ("ret", COND[y].to_string(), 1),
+            4 => (
+                "ldh",
+                format!("[{}], a", self::address(0xFF00 | n8 as u16)),
+                2,
+            ),
+            5 => ("add", format!("sp, {}", n8 as i8), 2),
+            6 => (
+                "ldh",
+                format!("a, [{}]", self::address(0xFF00 | n8 as u16)),
+                2,
+            ),
+            _ => ("ld", format!("hl, sp{:+}", n8 as i8), 2),
+        },
+        (3, 1) if q == 0 => ("pop", REG16_STACK[p].to_string(), 1),
+        (3, 1) => match p {
+            0 => ("ret", String::new(), 1),
+            1 => ("reti", String::new(), 1),
+            2 => ("jp", "hl".to_string(), 1),
+            _ => ("ld", "sp, hl".to_string(), 1),
+        },
+        (3, 2) => match y {
+            0..=3 => ("jp", format!("{}, ${:04x}", COND[y], n16), 3),
+            4 => ("ldh", "[c], a".to_string(), 1),
+            5 => ("ld", format!("[{}], a", self::address(n16)), 3),
+            6 => ("ldh", "a, [c]".to_string(), 1),
+            _ => ("ld", format!("a, [{}]", self::address(n16)), 3),
+        },
+        (3, 3) => match y {
+            0 => ("jp", format!("${:04x}", n16), 3),
+            1 => decode_cb(n8),
+            6 => ("di", String::new(), 1),
+            7 => ("ei", String::new(), 1),
+            _ => illegal(op),
+        },
+        (3, 4) if y < 4 => ("call", format!("{}, ${:04x}", COND[y], n16), 3),
+        (3, 5) if q == 0 => ("push", REG16_STACK[p].to_string(), 1),
+        (3, 5) if p == 0 => ("call", format!("${:04x}", n16), 3),
+        (3, 6) => alu(y, format!("${:02x}", n8), 2),
+        (3, 7) => ("rst", format!("${:02x}", y * 8), 1),
+        _ => illegal(op),
+    };
+    Instruction {
+        mnemonic,
+        operands,
+        len,
+    }
+}
+
+fn alu(y: usize, operand: String, len: u16) -> (&'static str, String, u16) {
+    // Only the ones that take the carry or could be confused with 16 bit adds name a
+    match y {
+        0 | 1 | 3 => (ALU[y], format!("a, {}", operand), len),
+        _ => (ALU[y], operand, len),
+    }
+}
+
+fn decode_cb(op: u8) -> (&'static str, String, u16) {
+    let (x, y, z) = (op >> 6, (op >> 3 & 7) as usize, (op & 7) as usize);
+    match x {
+        0 => (ROT[y], REG8[z].to_string(), 2),
+        1 => ("bit", format!("{}, {}", y, REG8[z]), 2),
+        2 => ("res", format!("{}, {}", y, REG8[z]), 2),
+        _ => ("set", format!("{}, {}", y, REG8[z]), 2),
+    }
+}
+
+/// Locks up the cpu, shown as data
+fn illegal(op: u8) -> (&'static str, String, u16) {
+    ("db", format!("${:02x}", op), 1)
+}
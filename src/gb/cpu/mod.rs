@@ -1,39 +1,75 @@
-use super::mmu::Mmu;
-
-use self::{
-    info::{CYCLES, CYCLES_CB, CYCLES_CB_BIT_HL},
-    registers::{Flag, Reg16, Reg8, Registers},
+use super::{
+    bus::Bus,
+    mmu::Mmu,
+    state::{StateError, StateReader, StateWriter},
 };
 
-pub mod info;
+use self::registers::{Flag, Reg16, Reg8, Registers};
+
+pub mod disasm;
 pub mod registers;
 #[cfg(test)]
 mod test;
 
 pub const HZ: u32 = 4194304; // 2^22
 
-pub struct Cpu {
+pub struct Cpu<B: Bus = Mmu> {
     pub r: Registers,
-    pub m: Mmu,
+    pub m: B,
     halt: bool,
     stop: bool,
     ime: bool,
+    /// Set by EI, ime is only set after the next instruction
     pending_ei: bool,
-    pending_di: bool,
     halt_bug: bool,
+    /// Pc and opcode of the illegal instruction that hung the cpu, only a reset gets it going again
+    locked: Option<(u16, u8)>,
+    /// T-cycles spent by the current step, 4 for every memory access or internal delay
+    cycles: u32,
 }
 
 impl Cpu {
-    pub fn new(mmu: Mmu) -> Self {
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.r.save_state(w);
+        self.m.save_state(w);
+        w.bool(self.halt);
+        w.bool(self.stop);
+        w.bool(self.ime);
+        w.bool(self.pending_ei);
+        w.bool(self.halt_bug);
+        w.bool(self.locked.is_some());
+        let (pc, opcode) = self.locked.unwrap_or_default();
+        w.u16(pc);
+        w.u8(opcode);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.r.load_state(r)?;
+        self.m.load_state(r)?;
+        self.halt = r.bool()?;
+        self.stop = r.bool()?;
+        self.ime = r.bool()?;
+        self.pending_ei = r.bool()?;
+        self.halt_bug = r.bool()?;
+        let locked = r.bool()?;
+        let (pc, opcode) = (r.u16()?, r.u8()?);
+        self.locked = if locked { Some((pc, opcode)) } else { None };
+        Ok(())
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn new(bus: B) -> Self {
         Self {
             r: Registers::new(),
-            m: mmu,
+            m: bus,
             halt: false,
             stop: false,
             ime: false,
             pending_ei: false,
-            pending_di: false,
             halt_bug: false,
+            locked: None,
+            cycles: 0,
         }
     }
 
@@ -42,54 +78,102 @@ impl Cpu {
         self.stop = false;
         self.ime = false;
         self.pending_ei = false;
-        self.pending_di = false;
         self.halt_bug = false;
+        self.locked = None;
+    }
+
+    pub fn locked(&self) -> Option<(u16, u8)> {
+        self.locked
+    }
+
+    /// Takes 5 M-cycles, 2 waiting, 2 pushing pc and 1 for the jump
+    fn dispatch_interrupt(&mut self) {
+        self.ime = false;
+        self.tick();
+        self.tick();
+        // EI; HALT with an interrupt pending triggers the halt bug, the interrupt returns to the HALT
+        let pc = if self.halt_bug {
+            self.halt_bug = false;
+            self.r.pc.wrapping_sub(1)
+        } else {
+            self.r.pc
+        };
+        let sp = self.r.get_sp_push();
+        self.write(sp.wrapping_add(1), (pc >> 8) as u8);
+        // The interrupt is only picked once the high byte is pushed, which can overwrite IE.
+        // Without anything left to handle the cpu jumps to 0x0000 instead
+        let handler = if self.m.has_pending_interrupts() {
+            let interrupt = self.m.next_interrupt();
+            self.m.disable_interrupt(interrupt);
+            interrupt.handler()
+        } else {
+            0x0000
+        };
+        self.write(sp, pc as u8);
+        self.r.pc = handler;
+        self.tick();
     }
 
-    pub fn toggle_interrupt(&mut self) {
-        if self.pending_di {
-            self.ime = false;
-            self.pending_di = false;
+    pub fn step(&mut self) -> (u16, u8, u32) {
+        let pc = self.r.pc;
+        let instr = self.m.peek(self.r.pc);
+        self.cycles = 0;
+        if self.locked.is_some() {
+            // The rest of the system keeps going, interrupts are ignored
+            self.tick();
+            return (pc, instr, self.cycles);
         }
-        if self.pending_ei {
+        if self.stop {
+            if !self.m.joypad_pressed() {
+                // Everything is stopped, time only passes for the frontend
+                self.cycles += 4;
+                return (pc, instr, self.cycles);
+            }
+            self.stop = false;
+        }
+        if self.halt {
+            if !self.m.has_pending_interrupts() {
+                self.tick();
+                return (pc, instr, self.cycles);
+            }
+            // Any pending interrupt wakes the cpu up, even with ime off, which takes an extra M-cycle
+            self.halt = false;
+            self.tick();
+        }
+        let enable_ime = self.pending_ei;
+        if self.ime && self.m.has_pending_interrupts() {
+            self.dispatch_interrupt();
+        } else {
+            self.step_instr();
+        }
+        // Still pending unless the instruction after EI was DI
+        if enable_ime && self.pending_ei {
             self.ime = true;
             self.pending_ei = false;
         }
+        (pc, instr, self.cycles)
     }
 
-    pub fn handle_interrupt(&mut self) -> bool {
-        if (self.ime || self.halt || self.stop) && self.m.has_pending_interrupts() {
-            self.halt = false;
-            self.stop = false;
-            if self.ime {
-                self.ime = false;
-                let interrupt = self.m.next_interrupt();
-                self.m.disable_interrupt(interrupt);
-                self.push(self.r.pc);
-                self.r.pc = self.m.get_interrupt_handler(interrupt);
-                return true;
-            }
-        }
-        false
+    /// Runs the other devices for one M-cycle
+    fn tick(&mut self) {
+        self.m.tick(4);
+        self.cycles += 4;
     }
 
-    pub fn step(&mut self) -> (u16, u8, u32) {
-        self.toggle_interrupt();
-        let pc = self.r.pc;
-        let instr = self.m.b(self.r.pc);
-        let cycles = if self.handle_interrupt() || self.halt {
-            4
-        } else {
-            self.step_instr()
-        };
-        self.m.step(cycles); // run other devices
-        (pc, instr, cycles)
+    fn read(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.m.read(address)
     }
 
-    fn alu_arg_get(&self, offset: u32) -> u8 {
+    fn write(&mut self, address: u16, value: u8) {
+        self.tick();
+        self.m.write(address, value);
+    }
+
+    fn alu_arg_get(&mut self, offset: u32) -> u8 {
         match offset {
             0..=5 => self.r.get_8(Reg8::get(offset + 2)),
-            6 => self.m.b(self.r.get_16(Reg16::HL)),
+            6 => self.read(self.r.get_16(Reg16::HL)),
             7 => self.r.get_8(Reg8::A),
             _ => unreachable!(),
         }
@@ -98,14 +182,14 @@ impl Cpu {
     fn alu_arg_set(&mut self, offset: u32, value: u8) {
         match offset {
             0..=5 => self.r.set_8(Reg8::get(offset + 2), value),
-            6 => self.m.wb(self.r.get_16(Reg16::HL), value),
+            6 => self.write(self.r.get_16(Reg16::HL), value),
             7 => self.r.set_8(Reg8::A, value),
             _ => unreachable!(),
         };
     }
 
     fn step_pc_b(&mut self) -> u8 {
-        let next = self.m.b(self.r.pc);
+        let next = self.read(self.r.pc);
         if self.halt_bug {
             self.halt_bug = false;
         } else {
@@ -115,26 +199,45 @@ impl Cpu {
     }
 
     fn step_pc_w(&mut self) -> u16 {
-        let next = self.m.w(self.r.pc);
+        let low = self.read(self.r.pc);
+        let high = self.read(self.r.pc.wrapping_add(1));
         self.r.pc = self.r.pc.wrapping_add(2);
-        next
+        (high as u16) << 8 | low as u16
     }
 
     fn pop(&mut self) -> u16 {
-        self.m.w(self.r.get_sp_pop())
+        let sp = self.r.get_sp_pop();
+        let low = self.read(sp);
+        let high = self.read(sp.wrapping_add(1));
+        (high as u16) << 8 | low as u16
     }
 
+    /// Includes the internal cycle that comes before the writes, high byte first
     fn push(&mut self, value: u16) {
-        self.m.ww(self.r.get_sp_push(), value)
+        self.tick();
+        let sp = self.r.get_sp_push();
+        self.write(sp.wrapping_add(1), (value >> 8) as u8);
+        self.write(sp, value as u8);
     }
 
-    fn step_instr(&mut self) -> u32 {
+    fn step_instr(&mut self) {
+        let pc = self.r.pc;
         let instr = self.step_pc_b();
-        let mut cycles = CYCLES[instr as usize];
         match instr {
             0x00 => { /* nop */ }
             0x10 => {
-                self.stop = true;
+                // stop, a pending interrupt makes it 1 byte long and a pressed button makes it a halt,
+                // or a nop if both happen. KEY1 speed switching is CGB only
+                let pending = self.m.has_pending_interrupts();
+                if !pending {
+                    self.step_pc_b();
+                }
+                if self.m.joypad_pressed() {
+                    self.halt = !pending;
+                } else {
+                    self.stop = true;
+                    self.m.stop();
+                }
             }
             0x01 | 0x11 | 0x21 | 0x31 => {
                 let dest = Reg16::get((instr as u32 / 16) + 1); // ld d16
@@ -147,11 +250,12 @@ impl Cpu {
                 } else {
                     self.r.get_hl(instr == 0x22)
                 };
-                self.m.wb(address, self.r.get_8(Reg8::A)); // ld (N16), a
+                self.write(address, self.r.get_8(Reg8::A)); // ld (N16), a
             }
             0x03 | 0x13 | 0x23 | 0x33 => {
                 let dest = Reg16::get((instr as u32 / 16) + 1); // inc16
-                self.r.set_16(dest, self.r.inc16(self.r.get_16(dest)))
+                self.r.set_16(dest, self.r.inc16(self.r.get_16(dest)));
+                self.tick();
             }
             0x04 | 0x14 | 0x24 => {
                 let dest = Reg8::get(((instr as u32 / 16) * 2) + 2); // inc
@@ -160,9 +264,9 @@ impl Cpu {
             }
             0x34 => {
                 let hl = self.r.get_16(Reg16::HL);
-                let val = self.m.b(hl);
+                let val = self.read(hl);
                 let res = self.r.inc(val);
-                self.m.wb(hl, res); // inc (hl)
+                self.write(hl, res); // inc (hl)
             }
             0x05 | 0x15 | 0x25 => {
                 let dest = Reg8::get(((instr as u32 / 16) * 2) + 2); // dec
@@ -171,9 +275,9 @@ impl Cpu {
             }
             0x35 => {
                 let hl = self.r.get_16(Reg16::HL);
-                let val = self.m.b(hl);
+                let val = self.read(hl);
                 let res = self.r.dec(val);
-                self.m.wb(hl, res); // dec (hl)
+                self.write(hl, res); // dec (hl)
             }
             0x06 | 0x16 | 0x26 => {
                 let imm = self.step_pc_b();
@@ -182,7 +286,7 @@ impl Cpu {
             }
             0x36 => {
                 let imm = self.step_pc_b();
-                self.m.wb(self.r.get_16(Reg16::HL), imm); // ld (hl) d8
+                self.write(self.r.get_16(Reg16::HL), imm); // ld (hl) d8
             }
             0x07 => {
                 let res = self.r.rlc(self.r.get_8(Reg8::A)); // rlc a
@@ -203,7 +307,9 @@ impl Cpu {
             }
             0x08 => {
                 let imm = self.step_pc_w(); // ld (a16) SP
-                self.m.ww(imm, self.r.get_16(Reg16::SP));
+                let sp = self.r.get_16(Reg16::SP);
+                self.write(imm, sp as u8);
+                self.write(imm.wrapping_add(1), (sp >> 8) as u8);
             }
             0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
                 // jr r8 | jr z, r8 | jr c, r8 | jr nz, r8 | jr nc, r8
@@ -222,13 +328,14 @@ impl Cpu {
                     } else {
                         self.r.pc = self.r.pc.wrapping_sub(imm.wrapping_neg() as u16);
                     }
-                    cycles += 4;
+                    self.tick();
                 }
             }
             0x09 | 0x19 | 0x29 | 0x39 => {
                 let src = Reg16::get((instr as u32 / 16) + 1); // add16
                 let res = self.r.add16(self.r.get_16(src));
                 self.r.set_16(Reg16::HL, res);
+                self.tick();
             }
             0x0A | 0x1A | 0x2A | 0x3A => {
                 let address = if instr < 0x2A {
@@ -236,11 +343,13 @@ impl Cpu {
                 } else {
                     self.r.get_hl(instr == 0x2A)
                 };
-                self.r.set_8(Reg8::A, self.m.b(address)); // ld a, (N16)
+                let res = self.read(address);
+                self.r.set_8(Reg8::A, res); // ld a, (N16)
             }
             0x0B | 0x1B | 0x2B | 0x3B => {
                 let dest = Reg16::get((instr as u32 / 16) + 1); // dec16
-                self.r.set_16(dest, self.r.dec16(self.r.get_16(dest)))
+                self.r.set_16(dest, self.r.dec16(self.r.get_16(dest)));
+                self.tick();
             }
             0x0C | 0x1C | 0x2C => {
                 let dest = Reg8::get(((instr as u32 / 16) * 2) + 3); // inc
@@ -354,18 +463,20 @@ impl Cpu {
                     0xD8 => self.r.get_flag(Flag::C),
                     _ => unreachable!(),
                 };
+                self.tick();
                 if cond {
                     self.r.pc = self.pop();
-                    cycles += 12;
+                    self.tick();
                 }
             }
             0xE0 => {
                 let imm = self.step_pc_b() as u16; // ldh (a8), a
-                self.m.wb(0xFF00 + imm, self.r.get_8(Reg8::A));
+                self.write(0xFF00 + imm, self.r.get_8(Reg8::A));
             }
             0xF0 => {
                 let imm = self.step_pc_b() as u16; // ldh a, (a8)
-                self.r.set_8(Reg8::A, self.m.b(0xFF00 + imm));
+                let res = self.read(0xFF00 + imm);
+                self.r.set_8(Reg8::A, res);
             }
             0xC1 | 0xD1 | 0xE1 | 0xF1 => {
                 let dest = if instr == 0xF1 {
@@ -378,11 +489,10 @@ impl Cpu {
             }
             0xE2 => {
                 // ld (c), a
-                self.m
-                    .wb(0xFF00 + self.r.get_8(Reg8::C) as u16, self.r.get_8(Reg8::A));
+                self.write(0xFF00 + self.r.get_8(Reg8::C) as u16, self.r.get_8(Reg8::A));
             }
             0xF2 => {
-                let res = self.m.b(0xFF00 + self.r.get_8(Reg8::C) as u16);
+                let res = self.read(0xFF00 + self.r.get_8(Reg8::C) as u16);
                 self.r.set_8(Reg8::A, res); // ld a, (c)
             }
             0xC2 | 0xD2 | 0xC3 | 0xCA | 0xDA => {
@@ -398,7 +508,7 @@ impl Cpu {
                 };
                 if cond {
                     self.r.pc = imm;
-                    cycles += 4;
+                    self.tick();
                 }
             }
             0xC5 | 0xD5 | 0xE5 | 0xF5 => {
@@ -435,13 +545,19 @@ impl Cpu {
                 let imm = self.step_pc_b() as i8;
                 let res = self.r.add16_imm_i8(sp, imm);
                 self.r.set_16(dest, res);
+                self.tick();
+                if instr == 0xE8 {
+                    self.tick();
+                }
             }
             0xC9 => {
                 self.r.pc = self.pop(); // ret
+                self.tick();
             }
             0xD9 => {
                 self.r.pc = self.pop(); // reti
-                self.pending_ei = true;
+                self.tick();
+                self.ime = true; // unlike EI there is no delay
             }
             0xE9 => {
                 // jp (hl)
@@ -450,20 +566,18 @@ impl Cpu {
             }
             0xF9 => {
                 self.r.set_16(Reg16::SP, self.r.get_16(Reg16::HL)); // ld sp, hl
+                self.tick();
             }
             0xEA => {
                 let imm = self.step_pc_w(); // ld (a16), a
-                self.m.wb(imm, self.r.get_8(Reg8::A));
+                self.write(imm, self.r.get_8(Reg8::A));
             }
             0xFA => {
                 let imm = self.step_pc_w();
-                let res = self.m.b(imm);
+                let res = self.read(imm);
                 self.r.set_8(Reg8::A, res); // ld a, (a16)
             }
-            0xCB => {
-                // CB instruction amounts already include the cost of CB itself. (speculation)
-                cycles = self.step_instr_cb();
-            }
+            0xCB => self.step_instr_cb(),
             0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => {
                 // call nz, a16 | call nc, a16 | call a16 | call z, a16 | call c, a16
                 let imm = self.step_pc_w();
@@ -478,7 +592,6 @@ impl Cpu {
                 if cond {
                     self.push(self.r.pc);
                     self.r.pc = imm;
-                    cycles += 12;
                 }
             }
             0xFE => {
@@ -486,20 +599,21 @@ impl Cpu {
                 self.r.cp(imm);
             }
             0xF3 => {
-                self.pending_di = true;
+                self.ime = false;
+                self.pending_ei = false;
             }
             0xFB => {
-                self.pending_ei = true;
+                if !self.ime {
+                    self.pending_ei = true;
+                }
             }
             0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
-                eprintln!("Invalid opcode: {:#04x}", instr);
-                unreachable!();
+                self.locked = Some((pc, instr));
             }
         };
-        cycles
     }
 
-    fn step_instr_cb(&mut self) -> u32 {
+    fn step_instr_cb(&mut self) {
         let instr = self.step_pc_b();
         match instr {
             0x00..=0x07 => {
@@ -568,9 +682,5 @@ impl Cpu {
                 self.alu_arg_set(offset, res);
             }
         }
-        match instr {
-            0x46 | 0x56 | 0x66 | 0x76 | 0x4E | 0x5E | 0x6E | 0x7E => CYCLES_CB_BIT_HL,
-            _ => CYCLES_CB[instr as usize % 16],
-        }
     }
 }
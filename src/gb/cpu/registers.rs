@@ -1,5 +1,7 @@
 use num_enum::UnsafeFromPrimitive;
 
+use crate::gb::state::{StateError, StateReader, StateWriter};
+
 pub struct Registers {
     r: [u8; 10], // a, f, b, c, d, e, h, l, sp
     pub pc: u16,
@@ -75,6 +77,17 @@ impl Registers {
         }
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.r);
+        w.u16(self.pc);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.r, "register file")?;
+        self.pc = r.u16()?;
+        Ok(())
+    }
+
     pub fn get_8(&self, r: Reg8) -> u8 {
         self.r[r as usize]
     }
@@ -2,10 +2,11 @@ use std::{fs::File, io::BufReader};
 
 use serde::Deserialize;
 
+use super::test_dir;
 use crate::gb::cpu::registers::{Reg8, Registers};
 
-const ALU_TEST_PATH: &'static str =
-    "C:\\Users\\user\\Documents\\Code\\rust-projects\\gameboy-test-data\\alu_tests\\v1\\";
+/// Directory with the alu_tests/v1 json files, the tests are ignored unless run with --ignored
+const ALU_TEST_DIR: &str = "GB_ALU_TEST_DIR";
 
 #[derive(Deserialize)]
 pub struct AluTest {
@@ -21,8 +22,8 @@ pub struct Result {
     flags: String,
 }
 
-fn reader(path: String) -> Option<BufReader<File>> {
-    if let Ok(file) = File::open(path) {
+fn reader(name: &str) -> Option<BufReader<File>> {
+    if let Ok(file) = File::open(test_dir(ALU_TEST_DIR).join(name)) {
         let reader = BufReader::new(file);
         Some(reader)
     } else {
@@ -40,8 +41,9 @@ fn to_hex(test: &AluTest) -> (u8, u8, u8, u8, u8) {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn adc() {
-    let reader = reader(format!("{}adc.json", ALU_TEST_PATH));
+    let reader = reader("adc.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -77,8 +79,9 @@ fn adc() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn add() {
-    let reader = reader(format!("{}add.json", ALU_TEST_PATH));
+    let reader = reader("add.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -114,8 +117,9 @@ fn add() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn and() {
-    let reader = reader(format!("{}and.json", ALU_TEST_PATH));
+    let reader = reader("and.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -151,8 +155,9 @@ fn and() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn bit() {
-    let reader = reader(format!("{}bit.json", ALU_TEST_PATH));
+    let reader = reader("bit.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -187,8 +192,9 @@ fn bit() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn ccf() {
-    let reader = reader(format!("{}ccf.json", ALU_TEST_PATH));
+    let reader = reader("ccf.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -223,8 +229,9 @@ fn ccf() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn cp() {
-    let reader = reader(format!("{}cp.json", ALU_TEST_PATH));
+    let reader = reader("cp.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -260,8 +267,9 @@ fn cp() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn cpl() {
-    let reader = reader(format!("{}cpl.json", ALU_TEST_PATH));
+    let reader = reader("cpl.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -297,8 +305,9 @@ fn cpl() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn daa() {
-    let reader = reader(format!("{}daa.json", ALU_TEST_PATH));
+    let reader = reader("daa.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -334,8 +343,9 @@ fn daa() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn or() {
-    let reader = reader(format!("{}or.json", ALU_TEST_PATH));
+    let reader = reader("or.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -371,8 +381,9 @@ fn or() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn res() {
-    let reader = reader(format!("{}res.json", ALU_TEST_PATH));
+    let reader = reader("res.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -407,8 +418,9 @@ fn res() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn rl() {
-    let reader = reader(format!("{}rl.json", ALU_TEST_PATH));
+    let reader = reader("rl.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -443,8 +455,9 @@ fn rl() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn rlc() {
-    let reader = reader(format!("{}rlc.json", ALU_TEST_PATH));
+    let reader = reader("rlc.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -479,8 +492,9 @@ fn rlc() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn rr() {
-    let reader = reader(format!("{}rr.json", ALU_TEST_PATH));
+    let reader = reader("rr.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -515,8 +529,9 @@ fn rr() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn rrc() {
-    let reader = reader(format!("{}rrc.json", ALU_TEST_PATH));
+    let reader = reader("rrc.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -551,8 +566,9 @@ fn rrc() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn sbc() {
-    let reader = reader(format!("{}sbc.json", ALU_TEST_PATH));
+    let reader = reader("sbc.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -588,8 +604,9 @@ fn sbc() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn scf() {
-    let reader = reader(format!("{}scf.json", ALU_TEST_PATH));
+    let reader = reader("scf.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -624,8 +641,9 @@ fn scf() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn set() {
-    let reader = reader(format!("{}set.json", ALU_TEST_PATH));
+    let reader = reader("set.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -660,8 +678,9 @@ fn set() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn sla() {
-    let reader = reader(format!("{}sla.json", ALU_TEST_PATH));
+    let reader = reader("sla.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -696,8 +715,9 @@ fn sla() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn sra() {
-    let reader = reader(format!("{}sra.json", ALU_TEST_PATH));
+    let reader = reader("sra.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -732,8 +752,9 @@ fn sra() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn srl() {
-    let reader = reader(format!("{}srl.json", ALU_TEST_PATH));
+    let reader = reader("srl.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -768,8 +789,9 @@ fn srl() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn sub() {
-    let reader = reader(format!("{}sub.json", ALU_TEST_PATH));
+    let reader = reader("sub.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -805,8 +827,9 @@ fn sub() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn swap() {
-    let reader = reader(format!("{}swap.json", ALU_TEST_PATH));
+    let reader = reader("swap.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
@@ -841,8 +864,9 @@ fn swap() {
 }
 
 #[test]
+#[ignore = "needs GB_ALU_TEST_DIR"]
 fn xor() {
-    let reader = reader(format!("{}xor.json", ALU_TEST_PATH));
+    let reader = reader("xor.json");
     assert!(reader.is_some());
     eprintln!("File reader created!");
     let tests: std::result::Result<Vec<AluTest>, serde_json::Error> =
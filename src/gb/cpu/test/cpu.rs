@@ -1,7 +1,19 @@
+use std::{fs, fs::File, io::BufReader};
+
 use serde::Deserialize;
 
-const CPU_TEST_PATH: &'static str =
-    "C:\\Users\\user\\Documents\\Code\\rust-projects\\gameboy-test-data\\cpu_tests\\v1\\";
+use super::test_dir;
+use crate::gb::{
+    bus::Bus,
+    cpu::{
+        registers::{Reg16, Reg8},
+        Cpu,
+    },
+    mmu::{Interrupt, INTE, INTF},
+};
+
+/// Directory with the single step cpu_tests/v1 json files, one per opcode, ignored unless run with --ignored
+const CPU_TEST_DIR: &str = "GB_CPU_TEST_DIR";
 
 #[derive(Debug, Deserialize)]
 pub struct CpuTest {
@@ -10,8 +22,9 @@ pub struct CpuTest {
     i: SystemState,
     #[serde(rename = "final")]
     f: SystemState,
+    /// Bus activity of each M-cycle, internal cycles are null or have no value
     #[serde(rename = "cycles")]
-    c: Vec<[String; 3]>,
+    c: Vec<Option<[Option<String>; 3]>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -98,3 +111,211 @@ fn deserialize_test() {
         }
     }
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// 64 KiB of plain ram that logs what the cpu does with it on every M-cycle
+struct TestBus {
+    ram: Vec<u8>,
+    /// None while the cpu is busy internally
+    cycles: Vec<Option<(u16, u8, Access)>>,
+}
+
+impl TestBus {
+    fn new() -> Self {
+        Self {
+            ram: vec![0; 0x10000],
+            cycles: vec![],
+        }
+    }
+
+    /// The cpu ticks before it reads or writes, so the access belongs to the last M-cycle
+    fn log(&mut self, address: u16, value: u8, access: Access) {
+        if let Some(cycle @ None) = self.cycles.last_mut() {
+            *cycle = Some((address, value, access));
+        }
+    }
+
+    fn pending(&self) -> u8 {
+        self.ram[INTE as usize] & self.ram[INTF as usize] & 0b00011111
+    }
+}
+
+impl Bus for TestBus {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.ram[address as usize];
+        self.log(address, value, Access::Read);
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.ram[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.ram[address as usize] = value;
+        self.log(address, value, Access::Write);
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            self.cycles.push(None);
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        self.pending() != 0
+    }
+
+    fn next_interrupt(&self) -> Interrupt {
+        Interrupt::highest(self.pending())
+    }
+
+    fn disable_interrupt(&mut self, i: Interrupt) {
+        self.ram[INTF as usize] &= !i.mask();
+    }
+
+    fn joypad_pressed(&self) -> bool {
+        false
+    }
+
+    fn stop(&mut self) {}
+}
+
+fn hex(s: &str) -> u16 {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16).unwrap()
+}
+
+const REGS: [(&str, Reg8); 8] = [
+    ("a", Reg8::A),
+    ("b", Reg8::B),
+    ("c", Reg8::C),
+    ("d", Reg8::D),
+    ("e", Reg8::E),
+    ("f", Reg8::F),
+    ("h", Reg8::H),
+    ("l", Reg8::L),
+];
+
+fn reg<'a>(state: &'a CpuState, name: &str) -> &'a str {
+    match name {
+        "a" => &state.a,
+        "b" => &state.b,
+        "c" => &state.c,
+        "d" => &state.d,
+        "e" => &state.e,
+        "f" => &state.f,
+        "h" => &state.h,
+        "l" => &state.l,
+        _ => unreachable!(),
+    }
+}
+
+/// Runs one test case, returns what went wrong
+fn run(test: &CpuTest) -> Result<(), String> {
+    let mut cpu = Cpu::new(TestBus::new());
+    for (name, r) in REGS {
+        cpu.r.set_8(r, hex(reg(&test.i.cpu, name)) as u8);
+    }
+    cpu.r.pc = hex(&test.i.cpu.pc);
+    cpu.r.set_16(Reg16::SP, hex(&test.i.cpu.sp));
+    for [address, value] in &test.i.ram {
+        cpu.m.ram[hex(address) as usize] = hex(value) as u8;
+    }
+
+    cpu.step();
+
+    let mut errors = vec![];
+    for (name, r) in REGS {
+        let expected = hex(reg(&test.f.cpu, name)) as u8;
+        if cpu.r.get_8(r) != expected {
+            errors.push(format!(
+                "{} {:#04x} != {:#04x}",
+                name,
+                cpu.r.get_8(r),
+                expected
+            ));
+        }
+    }
+    if cpu.r.pc != hex(&test.f.cpu.pc) {
+        errors.push(format!("pc {:#06x} != {}", cpu.r.pc, test.f.cpu.pc));
+    }
+    if cpu.r.get_16(Reg16::SP) != hex(&test.f.cpu.sp) {
+        let sp = cpu.r.get_16(Reg16::SP);
+        errors.push(format!("sp {:#06x} != {}", sp, test.f.cpu.sp));
+    }
+    for [address, value] in &test.f.ram {
+        let actual = cpu.m.ram[hex(address) as usize];
+        if actual != hex(value) as u8 {
+            errors.push(format!("({}) {:#04x} != {}", address, actual, value));
+        }
+    }
+    let expected: Vec<Option<(u16, u8, Access)>> = test
+        .c
+        .iter()
+        .map(|cycle| match cycle {
+            Some([Some(address), Some(value), Some(access)]) => match access.as_str() {
+                "read" => Some((hex(address), hex(value) as u8, Access::Read)),
+                "write" => Some((hex(address), hex(value) as u8, Access::Write)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    if cpu.m.cycles != expected {
+        errors.push(format!("cycles {:x?} != {:x?}", cpu.m.cycles, expected));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+#[test]
+#[ignore = "needs GB_CPU_TEST_DIR"]
+fn single_step() {
+    let dir = test_dir(CPU_TEST_DIR);
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "No tests in {}", dir.display());
+
+    let mut failed_opcodes = vec![];
+    for path in files {
+        let opcode = path.file_stem().unwrap().to_string_lossy().to_string();
+        let tests: Vec<CpuTest> =
+            match serde_json::from_reader(BufReader::new(File::open(&path).unwrap())) {
+                Ok(t) => t,
+                Err(e) => panic!("Could not deserialize {}: {}", path.display(), e),
+            };
+        let mut fail_count = 0;
+        for test in &tests {
+            if let Err(e) = run(test) {
+                // The first failure is usually enough to see what is wrong
+                if fail_count == 0 {
+                    eprintln!("[fail {}] {}", test.name, e);
+                }
+                fail_count += 1;
+            }
+        }
+        eprintln!(
+            "{}: {}/{} passed",
+            opcode,
+            tests.len() - fail_count,
+            tests.len()
+        );
+        if fail_count > 0 {
+            failed_opcodes.push(opcode);
+        }
+    }
+    assert!(failed_opcodes.is_empty(), "Failed: {:?}", failed_opcodes);
+}
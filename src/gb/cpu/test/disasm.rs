@@ -0,0 +1,182 @@
+use crate::gb::cpu::disasm::{decode, Instruction};
+
+/// Lengths of the unprefixed opcodes, the illegal ones are shown as 1 byte
+#[rustfmt::skip]
+const LENGTHS: [u16; 256] = [
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 1x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 2x
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 3x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 8x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9x
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Ax
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // Bx
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1, // Cx
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1, // Dx
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // Ex
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // Fx
+];
+
+const ILLEGAL: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+/// Decodes bytes placed at address, anything past them reads 0
+fn decode_at(address: u16, bytes: &[u8]) -> Instruction {
+    decode(address, |a| {
+        bytes
+            .get(a.wrapping_sub(address) as usize)
+            .copied()
+            .unwrap_or(0)
+    })
+}
+
+fn check(cases: &[(u16, &[u8], &str)]) {
+    for &(address, bytes, expected) in cases {
+        let instr = decode_at(address, bytes);
+        assert_eq!(instr.to_string(), expected, "{:02x?}", bytes);
+        assert_eq!(instr.len as usize, bytes.len(), "{:02x?}", bytes);
+    }
+}
+
+#[test]
+fn block_0() {
+    check(&[
+        (0x0000, &[0x00], "nop"),
+        (0x0000, &[0x08, 0x34, 0x12], "ld [$1234], sp"),
+        (0x0000, &[0x08, 0x0F, 0xFF], "ld [IF], sp"),
+        (0x0000, &[0x10, 0x00], "stop"),
+        (0x0150, &[0x18, 0xFE], "jr $0150"),
+        (0x0100, &[0x20, 0x05], "jr nz, $0107"),
+        (0x0200, &[0x38, 0x80], "jr c, $0182"),
+        (0xFFFE, &[0x18, 0x10], "jr $0010"),
+        (0x0000, &[0x01, 0x34, 0x12], "ld bc, $1234"),
+        (0x0000, &[0x31, 0xFE, 0xFF], "ld sp, $fffe"),
+        (0x0000, &[0x09], "add hl, bc"),
+        (0x0000, &[0x39], "add hl, sp"),
+        (0x0000, &[0x02], "ld [bc], a"),
+        (0x0000, &[0x1A], "ld a, [de]"),
+        (0x0000, &[0x22], "ld [hl+], a"),
+        (0x0000, &[0x3A], "ld a, [hl-]"),
+        (0x0000, &[0x03], "inc bc"),
+        (0x0000, &[0x3B], "dec sp"),
+        (0x0000, &[0x34], "inc [hl]"),
+        (0x0000, &[0x3D], "dec a"),
+        (0x0000, &[0x36, 0x42], "ld [hl], $42"),
+        (0x0000, &[0x0E, 0x00], "ld c, $00"),
+        (0x0000, &[0x07], "rlca"),
+        (0x0000, &[0x1F], "rra"),
+        (0x0000, &[0x27], "daa"),
+        (0x0000, &[0x2F], "cpl"),
+        (0x0000, &[0x37], "scf"),
+        (0x0000, &[0x3F], "ccf"),
+    ]);
+}
+
+#[test]
+fn block_1_and_2() {
+    check(&[
+        (0x0000, &[0x40], "ld b, b"),
+        (0x0000, &[0x41], "ld b, c"),
+        (0x0000, &[0x77], "ld [hl], a"),
+        (0x0000, &[0x7E], "ld a, [hl]"),
+        (0x0000, &[0x76], "halt"),
+        (0x0000, &[0x80], "add a, b"),
+        (0x0000, &[0x8E], "adc a, [hl]"),
+        (0x0000, &[0x90], "sub b"),
+        (0x0000, &[0x9F], "sbc a, a"),
+        (0x0000, &[0xA0], "and b"),
+        (0x0000, &[0xAF], "xor a"),
+        (0x0000, &[0xB6], "or [hl]"),
+        (0x0000, &[0xBF], "cp a"),
+    ]);
+}
+
+#[test]
+fn block_3() {
+    check(&[
+        (0x0000, &[0xC0], "ret nz"),
+        (0x0000, &[0xD8], "ret c"),
+        (0x0000, &[0xC9], "ret"),
+        (0x0000, &[0xD9], "reti"),
+        (0x0000, &[0xE0, 0x44], "ldh [LY], a"),
+        (0x0000, &[0xF0, 0x00], "ldh a, [P1]"),
+        (0x0000, &[0xF0, 0x80], "ldh a, [$ff80]"),
+        (0x0000, &[0xE0, 0xFF], "ldh [IE], a"),
+        (0x0000, &[0xE8, 0xFE], "add sp, -2"),
+        (0x0000, &[0xF8, 0x05], "ld hl, sp+5"),
+        (0x0000, &[0xF8, 0xFF], "ld hl, sp-1"),
+        (0x0000, &[0xC1], "pop bc"),
+        (0x0000, &[0xF1], "pop af"),
+        (0x0000, &[0xD5], "push de"),
+        (0x0000, &[0xF5], "push af"),
+        (0x0000, &[0xE9], "jp hl"),
+        (0x0000, &[0xF9], "ld sp, hl"),
+        (0x0000, &[0xC2, 0x00, 0x40], "jp nz, $4000"),
+        (0x0000, &[0xDA, 0x34, 0x12], "jp c, $1234"),
+        (0x0000, &[0xE2], "ldh [c], a"),
+        (0x0000, &[0xF2], "ldh a, [c]"),
+        (0x0000, &[0xEA, 0x00, 0xC0], "ld [$c000], a"),
+        (0x0000, &[0xEA, 0x40, 0xFF], "ld [LCDC], a"),
+        (0x0000, &[0xFA, 0x0F, 0xFF], "ld a, [IF]"),
+        (0x0000, &[0xC3, 0x50, 0x01], "jp $0150"),
+        (0x0000, &[0xF3], "di"),
+        (0x0000, &[0xFB], "ei"),
+        (0x0000, &[0xCC, 0x00, 0x20], "call z, $2000"),
+        (0x0000, &[0xD4, 0x00, 0x20], "call nc, $2000"),
+        (0x0000, &[0xCD, 0x00, 0x20], "call $2000"),
+        (0x0000, &[0xC6, 0x10], "add a, $10"),
+        (0x0000, &[0xDE, 0x01], "sbc a, $01"),
+        (0x0000, &[0xE6, 0x0F], "and $0f"),
+        (0x0000, &[0xFE, 0x90], "cp $90"),
+        (0x0000, &[0xC7], "rst $00"),
+        (0x0000, &[0xFF], "rst $38"),
+    ]);
+}
+
+#[test]
+fn cb_prefixed() {
+    check(&[
+        (0x0000, &[0xCB, 0x00], "rlc b"),
+        (0x0000, &[0xCB, 0x0F], "rrc a"),
+        (0x0000, &[0xCB, 0x11], "rl c"),
+        (0x0000, &[0xCB, 0x1A], "rr d"),
+        (0x0000, &[0xCB, 0x23], "sla e"),
+        (0x0000, &[0xCB, 0x2C], "sra h"),
+        (0x0000, &[0xCB, 0x37], "swap a"),
+        (0x0000, &[0xCB, 0x3E], "srl [hl]"),
+        (0x0000, &[0xCB, 0x7C], "bit 7, h"),
+        (0x0000, &[0xCB, 0x46], "bit 0, [hl]"),
+        (0x0000, &[0xCB, 0x86], "res 0, [hl]"),
+        (0x0000, &[0xCB, 0xBD], "res 7, l"),
+        (0x0000, &[0xCB, 0xC0], "set 0, b"),
+        (0x0000, &[0xCB, 0xFF], "set 7, a"),
+    ]);
+    for op in 0..=0xFF {
+        assert_eq!(decode_at(0, &[0xCB, op]).len, 2, "cb {:02x}", op);
+    }
+}
+
+#[test]
+fn illegal_opcodes() {
+    for op in ILLEGAL {
+        let instr = decode_at(0, &[op]);
+        assert_eq!(instr.to_string(), format!("db ${:02x}", op));
+        assert_eq!(instr.len, 1);
+    }
+    let db = (0..=0xFF)
+        .filter(|&op| decode_at(0, &[op]).mnemonic == "db")
+        .count();
+    assert_eq!(db, ILLEGAL.len());
+}
+
+#[test]
+fn lengths() {
+    for op in 0..=0xFF {
+        assert_eq!(decode_at(0, &[op]).len, LENGTHS[op as usize], "{:02x}", op);
+    }
+}
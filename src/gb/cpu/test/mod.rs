@@ -1,2 +1,14 @@
+use std::{env, path::PathBuf};
+
 mod alu;
 mod cpu;
+mod disasm;
+mod system;
+
+/// The test data isn't part of the repository, so its directory comes from an environment variable
+fn test_dir(var: &str) -> PathBuf {
+    match env::var_os(var) {
+        Some(dir) => PathBuf::from(dir),
+        None => panic!("{} has to be set to run this test", var),
+    }
+}
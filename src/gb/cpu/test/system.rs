@@ -0,0 +1,233 @@
+//! Short programs on a whole system, for the timing that depends on interrupts and the other devices
+
+use crate::gb::{
+    cpu::registers::{Reg16, Reg8},
+    joypad::{Button, P1},
+    test::{test_gb, test_gb_with},
+    Gb,
+};
+
+const DIV: u16 = 0xFF04;
+const LCDC: u16 = 0xFF40;
+const IF: u16 = 0xFF0F;
+const IE: u16 = 0xFFFF;
+const VBLANK: u8 = 0b00001;
+const TIMER: u8 = 0b00100;
+
+/// Turns the lcd off so the ppu doesn't request vblank on its own, then sets IE and IF
+fn with_interrupts(mut gb: Gb, enabled: u8, requested: u8) -> Gb {
+    gb.write(LCDC, 0);
+    gb.write(IE, enabled);
+    gb.write(IF, requested);
+    gb
+}
+
+/// Steps `count` instructions, returns the cycles of the last one
+fn steps(gb: &mut Gb, count: usize) -> u32 {
+    (0..count).map(|_| gb.step().2).last().unwrap_or(0)
+}
+
+fn stacked(gb: &Gb) -> u16 {
+    let sp = gb.registers().get_16(Reg16::SP);
+    u16::from_le_bytes([gb.read(sp), gb.read(sp.wrapping_add(1))])
+}
+
+#[test]
+fn ei_runs_the_next_instruction_first() {
+    // ld b, 0; ei; inc b; inc b
+    let mut gb = with_interrupts(test_gb(&[0x06, 0x00, 0xFB, 0x04, 0x04]), VBLANK, VBLANK);
+    steps(&mut gb, 3);
+    assert_eq!(gb.registers().pc, 0x0104);
+    assert_eq!(gb.registers().get_8(Reg8::B), 1);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0040);
+    assert_eq!(gb.registers().get_8(Reg8::B), 1);
+    assert_eq!(stacked(&gb), 0x0104);
+}
+
+#[test]
+fn ei_di_does_not_dispatch() {
+    // ei; di; nop; nop
+    let mut gb = with_interrupts(test_gb(&[0xFB, 0xF3]), VBLANK, VBLANK);
+    steps(&mut gb, 4);
+    assert_eq!(gb.registers().pc, 0x0104);
+    assert_eq!(gb.read(IF) & VBLANK, VBLANK);
+}
+
+#[test]
+fn dispatch_takes_5_m_cycles() {
+    // ei; nop
+    let mut gb = with_interrupts(test_gb(&[0xFB]), VBLANK, VBLANK);
+    steps(&mut gb, 2);
+    assert_eq!(steps(&mut gb, 1), 20);
+    assert_eq!(gb.registers().pc, 0x0040);
+    assert_eq!(stacked(&gb), 0x0102);
+    assert_eq!(gb.read(IF) & VBLANK, 0);
+}
+
+#[test]
+fn ie_overwritten_by_the_push_jumps_to_0() {
+    // ld sp, $0000; ei; nop, the high byte of pc lands in IE and disables the timer interrupt
+    let mut gb = with_interrupts(test_gb(&[0x31, 0x00, 0x00, 0xFB]), TIMER, TIMER);
+    steps(&mut gb, 3);
+    assert_eq!(steps(&mut gb, 1), 20);
+    assert_eq!(gb.registers().pc, 0x0000);
+    assert_eq!(gb.read(IE) & 0x1F, 0x01);
+    // Nothing was handled, so it is still requested
+    assert_eq!(gb.read(IF) & TIMER, TIMER);
+}
+
+#[test]
+fn waking_from_halt_takes_an_extra_m_cycle() {
+    // halt; nop
+    let mut gb = with_interrupts(test_gb(&[0x76]), VBLANK, 0);
+    steps(&mut gb, 1);
+    assert_eq!(steps(&mut gb, 1), 4);
+    assert_eq!(gb.registers().pc, 0x0101);
+    gb.write(IF, VBLANK);
+    // Woken up without ime, so the nop runs instead of the interrupt
+    assert_eq!(steps(&mut gb, 1), 4 + 4);
+    assert_eq!(gb.registers().pc, 0x0102);
+}
+
+#[test]
+fn waking_from_halt_with_ime_dispatches() {
+    // ei; halt
+    let mut gb = with_interrupts(test_gb(&[0xFB, 0x76]), VBLANK, 0);
+    steps(&mut gb, 2);
+    gb.write(IF, VBLANK);
+    assert_eq!(steps(&mut gb, 1), 4 + 20);
+    assert_eq!(gb.registers().pc, 0x0040);
+    assert_eq!(stacked(&gb), 0x0102);
+}
+
+#[test]
+fn ei_halt_with_a_pending_interrupt_returns_to_the_halt() {
+    let mut gb = with_interrupts(
+        test_gb_with(&[
+            // ld b, 0; ei; halt; ld c, b; jr -2
+            (0x0100, &[0x06, 0x00, 0xFB, 0x76, 0x48, 0x18, 0xFE]),
+            // inc b; xor a; ldh [IE], a; reti
+            (0x0040, &[0x04, 0xAF, 0xE0, 0xFF, 0xD9]),
+        ]),
+        VBLANK,
+        VBLANK,
+    );
+    let c = gb.registers().get_8(Reg8::C);
+    steps(&mut gb, 4);
+    assert_eq!(gb.registers().pc, 0x0040);
+    assert_eq!(stacked(&gb), 0x0103);
+    // The handler runs once and the halt after it never wakes up with IE cleared
+    steps(&mut gb, 40);
+    assert_eq!(gb.registers().get_8(Reg8::B), 1);
+    assert_eq!(gb.registers().get_8(Reg8::C), c);
+    assert_eq!(gb.registers().pc, 0x0104);
+}
+
+#[test]
+fn illegal_opcode_locks_the_cpu() {
+    let mut gb = test_gb(&[0xD3]);
+    assert_eq!(steps(&mut gb, 1), 4);
+    assert_eq!(gb.cpu_locked(), Some((0x0100, 0xD3)));
+    for _ in 0..10 {
+        assert_eq!(gb.step(), (0x0101, 0x00, 4));
+    }
+    assert_eq!(gb.cpu_locked(), Some((0x0100, 0xD3)));
+}
+
+#[test]
+fn locked_cpu_ignores_interrupts() {
+    // ei; db $fd
+    let mut gb = with_interrupts(test_gb(&[0xFB, 0xFD]), VBLANK, VBLANK);
+    steps(&mut gb, 2);
+    assert_eq!(gb.cpu_locked(), Some((0x0101, 0xFD)));
+    assert_eq!(steps(&mut gb, 10), 4);
+    assert_eq!(gb.registers().pc, 0x0102);
+    assert_eq!(gb.read(IF) & VBLANK, VBLANK);
+}
+
+#[test]
+fn reset_unlocks_the_cpu() {
+    let mut gb = test_gb(&[0xD3]);
+    steps(&mut gb, 2);
+    gb.reset();
+    assert_eq!(gb.cpu_locked(), None);
+    assert_eq!(gb.registers().pc, 0x0100);
+    steps(&mut gb, 1);
+    assert_eq!(gb.cpu_locked(), Some((0x0100, 0xD3)));
+}
+
+/// Selects the action buttons, so holding one of them pulls a line low
+fn with_joypad(mut gb: Gb, held: Option<Button>) -> Gb {
+    gb.write(P1, 0x10);
+    if let Some(button) = held {
+        gb.button_press(button);
+    }
+    gb
+}
+
+#[test]
+fn stop_waits_for_a_button() {
+    // stop; inc b
+    let mut gb = with_joypad(test_gb(&[0x10, 0x00, 0x04]), None);
+    let b = gb.registers().get_8(Reg8::B);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0102);
+    for _ in 0..10 {
+        assert_eq!(steps(&mut gb, 1), 4);
+        assert_eq!(gb.registers().pc, 0x0102);
+    }
+    // Reset by STOP and the timer doesn't run while stopped
+    assert_eq!(gb.read(DIV), 0);
+    gb.button_press(Button::Start);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0103);
+    assert_eq!(gb.registers().get_8(Reg8::B), b.wrapping_add(1));
+}
+
+#[test]
+fn stop_with_a_button_held_halts() {
+    // stop; nop
+    let gb = with_joypad(test_gb(&[0x10, 0x00]), Some(Button::A));
+    let mut gb = with_interrupts(gb, VBLANK, 0);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0102);
+    // Unlike STOP time goes on
+    steps(&mut gb, 100);
+    assert_eq!(gb.registers().pc, 0x0102);
+    assert_ne!(gb.read(DIV), 0);
+    // Woken up like a halt, with the extra M-cycle
+    gb.write(IF, VBLANK);
+    assert_eq!(steps(&mut gb, 1), 4 + 4);
+    assert_eq!(gb.registers().pc, 0x0103);
+}
+
+#[test]
+fn stop_with_an_interrupt_pending_is_1_byte() {
+    // stop; inc b
+    let gb = with_joypad(test_gb(&[0x10, 0x04]), None);
+    let mut gb = with_interrupts(gb, VBLANK, VBLANK);
+    let b = gb.registers().get_8(Reg8::B);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0101);
+    steps(&mut gb, 10);
+    assert_eq!(gb.registers().pc, 0x0101);
+    assert_eq!(gb.read(DIV), 0);
+    gb.button_press(Button::B);
+    steps(&mut gb, 1);
+    assert_eq!(gb.registers().pc, 0x0102);
+    assert_eq!(gb.registers().get_8(Reg8::B), b.wrapping_add(1));
+}
+
+#[test]
+fn stop_with_a_button_held_and_an_interrupt_pending_is_a_nop() {
+    // stop; inc b
+    let gb = with_joypad(test_gb(&[0x10, 0x04]), Some(Button::A));
+    let mut gb = with_interrupts(gb, VBLANK, VBLANK);
+    let b = gb.registers().get_8(Reg8::B);
+    assert_eq!(steps(&mut gb, 1), 4);
+    assert_eq!(gb.registers().pc, 0x0101);
+    assert_eq!(steps(&mut gb, 1), 4);
+    assert_eq!(gb.registers().pc, 0x0102);
+    assert_eq!(gb.registers().get_8(Reg8::B), b.wrapping_add(1));
+}
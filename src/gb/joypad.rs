@@ -1,5 +1,7 @@
 use num_enum::UnsafeFromPrimitive;
 
+use super::state::{StateError, StateReader, StateWriter};
+
 pub const P1: u16 = 0xFF00;
 
 pub struct Joypad {
@@ -8,6 +10,53 @@ pub struct Joypad {
     actions: u8,
     read_type: ReadType,
     interrupt_request: bool,
+    /// Buttons held by the player, see Button::mask
+    held: u8,
+    /// Buttons held with turbo, pressed and released every turbo_frames frames
+    turbo: u8,
+    /// The newer button of each pair of opposite directions
+    last_pressed: u8,
+    frames: u16,
+    pub options: JoypadOptions,
+}
+
+/// Settings that change how held buttons are seen by the game, movies have to be replayed with the same ones
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JoypadOptions {
+    pub opposite_policy: OppositePolicy,
+    /// Frames a turbo button stays pressed and then released, at least 1
+    pub turbo_frames: u8,
+}
+
+/// What happens when opposite directions are held at the same time, which a real d-pad can't do
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OppositePolicy {
+    /// Both are pressed, some games crash on this but movies and TAS tools need it
+    Allow,
+    /// The newer one is ignored until the other one is released
+    Block,
+    /// The newer one wins, the older one comes back once it is released
+    LastWins,
+}
+
+impl OppositePolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "allow" => Ok(OppositePolicy::Allow),
+            "block" => Ok(OppositePolicy::Block),
+            "last" => Ok(OppositePolicy::LastWins),
+            _ => Err("expected allow, block or last".to_string()),
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(OppositePolicy::Allow),
+            1 => Some(OppositePolicy::Block),
+            2 => Some(OppositePolicy::LastWins),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)] // Doesn't understand UnsafeFromPrimitive uses all the values
@@ -32,6 +81,34 @@ pub enum Button {
     Start,
 }
 
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit of the button in a mask of held buttons, directions in the low nibble like P1
+    pub fn mask(&self) -> u8 {
+        1 << Self::ALL.iter().position(|b| b == self).unwrap()
+    }
+
+    fn opposite(&self) -> Option<Button> {
+        match self {
+            Button::Right => Some(Button::Left),
+            Button::Left => Some(Button::Right),
+            Button::Up => Some(Button::Down),
+            Button::Down => Some(Button::Up),
+            _ => None,
+        }
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Self {
@@ -40,6 +117,14 @@ impl Joypad {
             actions: 0b00011111,
             read_type: ReadType::None,
             interrupt_request: false,
+            held: 0,
+            turbo: 0,
+            last_pressed: 0,
+            frames: 0,
+            options: JoypadOptions {
+                opposite_policy: OppositePolicy::Allow,
+                turbo_frames: 2,
+            },
         }
     }
 
@@ -49,6 +134,35 @@ impl Joypad {
         self.actions = 0b11011111;
         self.read_type = ReadType::None;
         self.interrupt_request = false;
+        self.held = 0;
+        self.turbo = 0;
+        self.last_pressed = 0;
+        self.frames = 0;
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.m_p1);
+        w.u8(self.directions);
+        w.u8(self.actions);
+        w.u8(self.read_type as u8);
+        w.bool(self.interrupt_request);
+        w.u8(self.held);
+        w.u8(self.turbo);
+        w.u8(self.last_pressed);
+        w.u16(self.frames);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.m_p1 = r.u8()?;
+        self.directions = r.u8()?;
+        self.actions = r.u8()?;
+        self.read_type = unsafe { ReadType::from_unchecked(r.u8()? & 0b11) };
+        self.interrupt_request = r.bool()?;
+        self.held = r.u8()?;
+        self.turbo = r.u8()?;
+        self.last_pressed = r.u8()?;
+        self.frames = r.u16()?;
+        Ok(())
     }
 
     pub fn step(&mut self) -> bool {
@@ -75,32 +189,83 @@ impl Joypad {
         self.m_p1 = new_value;
     }
 
-    pub fn release(&mut self, button: Button) {
-        match button {
-            Button::Right => self.directions |= 0b0001,
-            Button::Left => self.directions |= 0b0010,
-            Button::Up => self.directions |= 0b0100,
-            Button::Down => self.directions |= 0b1000,
-            Button::A => self.actions |= 0b0001,
-            Button::B => self.actions |= 0b0010,
-            Button::Select => self.actions |= 0b0100,
-            Button::Start => self.actions |= 0b1000,
+    /// A pressed button pulls its line low, if the game selected it
+    pub fn line_low(&self) -> bool {
+        self.m_p1 & 0x0F != 0x0F
+    }
+
+    /// Mask of the buttons held by the player, see Button::mask
+    pub fn buttons(&self) -> u8 {
+        self.held
+    }
+
+    /// Mask of the buttons held with turbo
+    pub fn turbo_buttons(&self) -> u8 {
+        self.turbo
+    }
+
+    /// Works out what the game sees from the held buttons, turbo and the opposite direction policy
+    fn update(&mut self) {
+        let mut pressed = self.held;
+        // On for turbo_frames, then off for as long
+        if self.frames < self.options.turbo_frames.max(1) as u16 {
+            pressed |= self.turbo;
         }
+        for pair in [
+            Button::Right.mask() | Button::Left.mask(),
+            Button::Up.mask() | Button::Down.mask(),
+        ] {
+            if pressed & pair == pair {
+                match self.options.opposite_policy {
+                    OppositePolicy::Allow => (),
+                    OppositePolicy::Block => pressed &= !(pair & self.last_pressed),
+                    OppositePolicy::LastWins => pressed &= !(pair & !self.last_pressed),
+                }
+            }
+        }
+        self.directions = (self.directions & 0xF0) | (!pressed & 0x0F);
+        self.actions = (self.actions & 0xF0) | (!pressed >> 4 & 0x0F);
         self.set_p1();
     }
 
+    fn pressed_last(&mut self, button: Button) {
+        if let Some(opposite) = button.opposite() {
+            self.last_pressed = (self.last_pressed & !opposite.mask()) | button.mask();
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.held &= !button.mask();
+        self.update();
+    }
+
     pub fn press(&mut self, button: Button) {
-        match button {
-            Button::Right => self.directions &= !0b0001,
-            Button::Left => self.directions &= !0b0010,
-            Button::Up => self.directions &= !0b0100,
-            Button::Down => self.directions &= !0b1000,
-            Button::A => self.actions &= !0b0001,
-            Button::B => self.actions &= !0b0010,
-            Button::Select => self.actions &= !0b0100,
-            Button::Start => self.actions &= !0b1000,
+        self.held |= button.mask();
+        self.pressed_last(button);
+        self.update();
+    }
+
+    pub fn release_turbo(&mut self, button: Button) {
+        self.turbo &= !button.mask();
+        self.update();
+    }
+
+    /// Starts with the button pressed
+    pub fn press_turbo(&mut self, button: Button) {
+        if self.turbo == 0 {
+            self.frames = 0;
+        }
+        self.turbo |= button.mask();
+        self.pressed_last(button);
+        self.update();
+    }
+
+    /// Advances turbo, call once per frame
+    pub fn frame(&mut self) {
+        if self.turbo != 0 {
+            self.frames = (self.frames + 1) % (self.options.turbo_frames.max(1) as u16 * 2);
+            self.update();
         }
-        self.set_p1();
     }
 
     pub fn b(&self, address: u16) -> u8 {
@@ -120,3 +285,132 @@ impl Joypad {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn joypad(opposite_policy: OppositePolicy) -> Joypad {
+        let mut joypad = Joypad::new();
+        joypad.reset();
+        joypad.options = JoypadOptions {
+            opposite_policy,
+            turbo_frames: 2,
+        };
+        joypad
+    }
+
+    /// Buttons the game reads through P1, in the same layout as Button::mask
+    fn seen(joypad: &mut Joypad) -> u8 {
+        joypad.wb(P1, 0x20);
+        let directions = !joypad.b(P1) & 0x0F;
+        joypad.wb(P1, 0x10);
+        let actions = !joypad.b(P1) & 0x0F;
+        directions | actions << 4
+    }
+
+    #[test]
+    fn allow_opposites() {
+        let mut joypad = joypad(OppositePolicy::Allow);
+        joypad.press(Button::Right);
+        joypad.press(Button::Left);
+        assert_eq!(
+            seen(&mut joypad),
+            Button::Right.mask() | Button::Left.mask()
+        );
+    }
+
+    #[test]
+    fn block_opposites() {
+        let mut joypad = joypad(OppositePolicy::Block);
+        joypad.press(Button::Up);
+        joypad.press(Button::Down);
+        joypad.press(Button::Right);
+        assert_eq!(seen(&mut joypad), Button::Up.mask() | Button::Right.mask());
+        joypad.release(Button::Up);
+        assert_eq!(
+            seen(&mut joypad),
+            Button::Down.mask() | Button::Right.mask()
+        );
+        // Pressing the first one again makes it the newer one, so it is blocked
+        joypad.press(Button::Up);
+        assert_eq!(
+            seen(&mut joypad),
+            Button::Down.mask() | Button::Right.mask()
+        );
+    }
+
+    #[test]
+    fn last_wins_opposites() {
+        let mut joypad = joypad(OppositePolicy::LastWins);
+        joypad.press(Button::Left);
+        joypad.press(Button::Right);
+        assert_eq!(seen(&mut joypad), Button::Right.mask());
+        joypad.press(Button::Left);
+        assert_eq!(seen(&mut joypad), Button::Left.mask());
+        joypad.release(Button::Left);
+        assert_eq!(seen(&mut joypad), Button::Right.mask());
+    }
+
+    #[test]
+    fn opposite_policy_applies_to_turbo() {
+        let mut joypad = joypad(OppositePolicy::LastWins);
+        joypad.press(Button::Left);
+        joypad.press_turbo(Button::Right);
+        assert_eq!(seen(&mut joypad), Button::Right.mask());
+        // While turbo is released the held direction shows through
+        joypad.frame();
+        joypad.frame();
+        assert_eq!(seen(&mut joypad), Button::Left.mask());
+    }
+
+    #[test]
+    fn turbo_toggles_every_turbo_frames() {
+        let mut joypad = joypad(OppositePolicy::Allow);
+        joypad.options.turbo_frames = 2;
+        joypad.press_turbo(Button::A);
+        let mut pattern = vec![];
+        for _ in 0..8 {
+            pattern.push(seen(&mut joypad) != 0);
+            joypad.frame();
+        }
+        assert_eq!(
+            pattern,
+            [true, true, false, false, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn turbo_restarts_pressed() {
+        let mut joypad = joypad(OppositePolicy::Allow);
+        joypad.press_turbo(Button::A);
+        joypad.frame();
+        joypad.frame();
+        assert_eq!(seen(&mut joypad), 0);
+        // Another turbo button joins the running phase
+        joypad.press_turbo(Button::B);
+        assert_eq!(seen(&mut joypad), 0);
+        // Once all are released the next one starts pressed
+        joypad.release_turbo(Button::A);
+        joypad.release_turbo(Button::B);
+        joypad.press_turbo(Button::B);
+        assert_eq!(seen(&mut joypad), Button::B.mask());
+    }
+
+    #[test]
+    fn held_buttons_ignore_turbo() {
+        let mut joypad = joypad(OppositePolicy::Allow);
+        joypad.press(Button::A);
+        joypad.press_turbo(Button::A);
+        for _ in 0..4 {
+            assert_eq!(seen(&mut joypad), Button::A.mask());
+            joypad.frame();
+        }
+        // Turbo takes over again once the button is let go
+        joypad.release(Button::A);
+        assert_eq!(seen(&mut joypad), Button::A.mask());
+        joypad.frame();
+        joypad.frame();
+        assert_eq!(seen(&mut joypad), 0);
+    }
+}
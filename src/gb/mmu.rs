@@ -1,7 +1,16 @@
 use num_enum::{IntoPrimitive, UnsafeFromPrimitive};
 
 use super::{
-    apu::Apu, bios::Bios, cart::Cartridge, joypad::Joypad, ppu::Ppu, serial::Serial, timer::Timer,
+    apu::Apu,
+    bios::Bios,
+    bus::Bus,
+    cart::Cartridge,
+    joypad::Joypad,
+    ppu::Ppu,
+    serial::Serial,
+    state::{StateError, StateReader, StateWriter},
+    timer::{Timer, DIV},
+    watch::{Access, Watchpoint},
 };
 
 // Sizes
@@ -54,6 +63,9 @@ pub struct Mmu {
     m_inte: u8,
     bios_mapped: bool,
     pub enable_bios: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    /// First access of the current instruction that hit a watchpoint
+    pub watch_hit: Option<Access>,
 }
 
 #[allow(dead_code)] // Doesn't understand UnsafeFromPrimitive uses all the values
@@ -67,6 +79,33 @@ pub enum Interrupt {
     Joypad = 4,
 }
 
+impl Interrupt {
+    /// The interrupt with the highest priority in a mask of IE & IF
+    pub fn highest(pending: u8) -> Interrupt {
+        if pending & 0b00001 != 0 {
+            Interrupt::VBlank
+        } else if pending & 0b00010 != 0 {
+            Interrupt::LCDStat
+        } else if pending & 0b00100 != 0 {
+            Interrupt::Timer
+        } else if pending & 0b01000 != 0 {
+            Interrupt::Serial
+        } else if pending & 0b10000 != 0 {
+            Interrupt::Joypad
+        } else {
+            unreachable!();
+        }
+    }
+
+    pub fn mask(self) -> u8 {
+        1 << <Interrupt as Into<u8>>::into(self)
+    }
+
+    pub fn handler(self) -> u16 {
+        0x0040 + (8 * <Interrupt as Into<u8>>::into(self)) as u16
+    }
+}
+
 impl Mmu {
     pub fn new(bios: Option<Bios>, cart: Cartridge) -> Self {
         let use_bios = bios.is_some();
@@ -84,6 +123,8 @@ impl Mmu {
             m_inte: 0x00,
             bios_mapped: use_bios,
             enable_bios: use_bios,
+            watchpoints: Vec::new(),
+            watch_hit: None,
         };
         mmu.reset();
         mmu
@@ -127,36 +168,36 @@ impl Mmu {
         self.wb(0xFF77, 0x00);
 
         self.m_inte = 0x00;
+        self.watch_hit = None;
     }
 
-    pub fn has_pending_interrupts(&self) -> bool {
-        (self.m_inte & self.m_intf) != 0
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.apu.save_state(w);
+        self.cart.save_state(w);
+        self.joypad.save_state(w);
+        self.ppu.save_state(w);
+        self.serial.save_state(w);
+        self.timer.save_state(w);
+        w.bytes(&self.m_wram);
+        w.bytes(&self.m_hram);
+        w.u8(self.m_intf);
+        w.u8(self.m_inte);
+        w.bool(self.bios_mapped);
     }
 
-    pub fn next_interrupt(&self) -> Interrupt {
-        let pending = self.m_inte & self.m_intf;
-        if pending & 0b00001 != 0 {
-            Interrupt::VBlank
-        } else if pending & 0b00010 != 0 {
-            Interrupt::LCDStat
-        } else if pending & 0b00100 != 0 {
-            Interrupt::Timer
-        } else if pending & 0b01000 != 0 {
-            Interrupt::Serial
-        } else if pending & 0b10000 != 0 {
-            Interrupt::Joypad
-        } else {
-            unreachable!();
-        }
-    }
-
-    pub fn disable_interrupt(&mut self, i: Interrupt) {
-        let mask: u8 = 1 << <Interrupt as Into<u8>>::into(i);
-        self.m_intf &= !mask;
-    }
-
-    pub fn get_interrupt_handler(&self, i: Interrupt) -> u16 {
-        0x0040 + (8 * <Interrupt as Into<u8>>::into(i)) as u16
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.apu.load_state(r)?;
+        self.cart.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.timer.load_state(r)?;
+        r.bytes_into(&mut self.m_wram, "wram")?;
+        r.bytes_into(&mut self.m_hram, "hram")?;
+        self.m_intf = r.u8()?;
+        self.m_inte = r.u8()?;
+        self.bios_mapped = r.bool()?;
+        Ok(())
     }
 
     pub fn step(&mut self, cycles: u32) {
@@ -204,21 +245,23 @@ impl Mmu {
         }
     }
 
-    pub fn w(&self, address: u16) -> u16 {
-        (self.b(address + 1) as u16) << 8 | (self.b(address) as u16)
+    fn watch(&mut self, access: Access) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.hit(access)) {
+            self.watch_hit = Some(access);
+        }
     }
 
     fn dma_transfer(&mut self, value: u8) {
         // KLUDGE:
         // Typically OAM transfer takes 160 cycles but the CPU can only access HRAM and usually just busy idles
         // So effectively we can skip the cycle accuracy and memory restrictions and just copy the whole memory at once.
-        let src = (value as u16) << 8;
-        if src <= 0xDF00 {
-            for i in 0..=0x9F {
-                self.ppu.wb(0xFE00 + i, self.b(src + i));
-            }
-        } else {
-            unreachable!();
+        // Sources past 0xDF00 read from wram like the echo range does
+        let src = match (value as u16) << 8 {
+            src @ 0xE000..=0xFF00 => src - 0x2000,
+            src => src,
+        };
+        for i in 0..=0x9F {
+            self.ppu.wb(0xFE00 + i, self.b(src + i));
         }
     }
 
@@ -259,9 +302,55 @@ impl Mmu {
             INTE => self.m_inte = value & 0b00011111,
         }
     }
+}
+
+// Watchpoints are checked here instead of in b and wb so only the cpu triggers them, not DMA or debug views
+impl Bus for Mmu {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.b(address);
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Read { address, value });
+        }
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.b(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.b(address);
+            self.watch(Access::Write {
+                address,
+                old,
+                value,
+            });
+        }
+        self.wb(address, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.step(cycles)
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        (self.m_inte & self.m_intf) != 0
+    }
+
+    fn next_interrupt(&self) -> Interrupt {
+        Interrupt::highest(self.m_inte & self.m_intf)
+    }
+
+    fn disable_interrupt(&mut self, i: Interrupt) {
+        self.m_intf &= !i.mask();
+    }
+
+    fn joypad_pressed(&self) -> bool {
+        self.joypad.line_low()
+    }
 
-    pub fn ww(&mut self, address: u16, value: u16) {
-        self.wb(address, (value & 0xFF) as u8);
-        self.wb(address + 1, (value >> 8) as u8);
+    fn stop(&mut self) {
+        self.timer.wb(DIV, 0);
     }
 }
@@ -2,55 +2,88 @@ use self::{
     bios::Bios,
     cart::{info::CartridgeInfo, Cartridge},
     cpu::{
-        registers::{Reg16, Reg8},
+        disasm::{decode, Instruction},
+        registers::{Reg16, Reg8, Registers},
         Cpu,
     },
-    joypad::Button,
+    joypad::{Button, JoypadOptions},
     mmu::Mmu,
-    ppu::{LCD_HEIGHT, LCD_WIDTH, ONE_FRAME_CYCLES},
+    ppu::{Layer, Ppu, LCD_HEIGHT, LCD_WIDTH, ONE_FRAME_CYCLES},
+    state::{read_header, write_header, StateError, StateReader, StateWriter},
+    watch::{Access, Watchpoint},
 };
 
 pub mod apu;
 pub mod bios;
+pub mod bus;
 pub mod cart;
 pub mod cpu;
 pub mod joypad;
 pub mod mmu;
 pub mod ppu;
 pub mod serial;
+pub mod state;
 pub mod timer;
+pub mod watch;
 
 pub struct Gb {
     cpu: Cpu,
+    /// Pc of the instruction and the access that first hit a watchpoint since it was last taken
+    watch_hit: Option<(u16, Access)>,
 }
 
 impl Gb {
     pub fn new(cart: Cartridge, bios: Option<Bios>) -> Self {
         let mmu = Mmu::new(bios, cart);
         let cpu = Cpu::new(mmu);
-        let mut gb = Self { cpu };
+        let mut gb = Self {
+            cpu,
+            watch_hit: None,
+        };
         gb.reset();
         gb
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
-        self.cpu
-            .r
-            .reset(self.cpu.m.enable_bios && self.cpu.m.bios.is_some());
+        self.cpu.r.reset(self.uses_bios());
         self.cpu.m.reset();
+        self.watch_hit = None;
     }
 
     pub fn step(&mut self) -> (u16, u8, u32) {
-        self.cpu.step()
+        let step = self.cpu.step();
+        if let Some(access) = self.cpu.m.watch_hit.take() {
+            self.watch_hit.get_or_insert((step.0, access));
+        }
+        step
     }
 
     pub fn step_frame(&mut self, mut cycles: u32) -> u32 {
         while cycles < ONE_FRAME_CYCLES {
-            cycles += self.cpu.step().2;
+            cycles += self.step().2;
+        }
+        self.end_frame(cycles)
+    }
+
+    /// Like step_frame but stops right after an instruction that hits a watchpoint.
+    /// Returns the cycles and whether the frame finished, an unfinished frame goes on when this
+    /// is called again with the returned cycles
+    pub fn step_until_watch(&mut self, mut cycles: u32) -> (u32, bool) {
+        while cycles < ONE_FRAME_CYCLES {
+            // A hit that was never taken does not stop every instruction after it
+            let taken = self.watch_hit.is_none();
+            cycles += self.step().2;
+            if taken && self.watch_hit.is_some() {
+                return (cycles, false);
+            }
         }
-        cycles -= ONE_FRAME_CYCLES;
-        cycles // carry over remaining cycles
+        (self.end_frame(cycles), true)
+    }
+
+    fn end_frame(&mut self, cycles: u32) -> u32 {
+        self.cpu.m.joypad.frame();
+        cycles - ONE_FRAME_CYCLES // carry over remaining cycles
     }
 
     pub fn get_buf(&self) -> [[u8; LCD_WIDTH]; LCD_HEIGHT] {
@@ -65,14 +98,139 @@ impl Gb {
         self.cpu.m.joypad.press(button)
     }
 
+    pub fn buttons(&self) -> u8 {
+        self.cpu.m.joypad.buttons()
+    }
+
+    /// Presses and releases whatever differs from a mask of held buttons, see Button::mask
+    pub fn set_buttons(&mut self, buttons: u8) {
+        let changed = buttons ^ self.buttons();
+        for button in Button::ALL {
+            if changed & button.mask() != 0 {
+                if buttons & button.mask() != 0 {
+                    self.button_press(button);
+                } else {
+                    self.button_release(button);
+                }
+            }
+        }
+    }
+
+    pub fn turbo_buttons(&self) -> u8 {
+        self.cpu.m.joypad.turbo_buttons()
+    }
+
+    /// Like set_buttons for buttons held with turbo
+    pub fn set_turbo(&mut self, buttons: u8) {
+        let changed = buttons ^ self.turbo_buttons();
+        for button in Button::ALL {
+            if changed & button.mask() != 0 {
+                if buttons & button.mask() != 0 {
+                    self.cpu.m.joypad.press_turbo(button);
+                } else {
+                    self.cpu.m.joypad.release_turbo(button);
+                }
+            }
+        }
+    }
+
+    pub fn joypad_options(&self) -> JoypadOptions {
+        self.cpu.m.joypad.options
+    }
+
+    pub fn set_joypad_options(&mut self, options: JoypadOptions) {
+        self.cpu.m.joypad.options = options;
+    }
+
+    /// Pc and opcode of the illegal instruction the cpu hung on
+    pub fn cpu_locked(&self) -> Option<(u16, u8)> {
+        self.cpu.locked()
+    }
+
+    /// Whether the system boots through the bios
+    pub fn uses_bios(&self) -> bool {
+        self.cpu.m.enable_bios && self.cpu.m.bios.is_some()
+    }
+
     pub fn cart_info(&self) -> &CartridgeInfo {
         &self.cpu.m.cart.info
     }
 
+    /// Snapshot of the whole machine, tagged with the cartridge it belongs to
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        write_header(&mut w, self.cart_info());
+        self.cpu.save_state(&mut w);
+        w.into_bytes()
+    }
+
+    /// Restores a snapshot from save_state, a state that fails to load leaves the machine as it was
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(bytes);
+        read_header(&mut r, self.cart_info())?;
+        let backup = self.save_state();
+        let result = self.cpu.load_state(&mut r).and_then(|_| r.finish());
+        if result.is_err() {
+            let mut r = StateReader::new(&backup);
+            read_header(&mut r, self.cart_info())
+                .and_then(|_| self.cpu.load_state(&mut r))
+                .expect("Failed to restore the state before loading");
+        }
+        result
+    }
+
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        self.cpu.m.ppu.toggle_layer(layer)
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.cpu.m.ppu
+    }
+
     pub(crate) fn next_step(&self) -> (u16, u8) {
         (self.cpu.r.pc, self.cpu.m.b(self.cpu.r.pc))
     }
 
+    pub fn registers(&self) -> &Registers {
+        &self.cpu.r
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.cpu.r
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.cpu.m.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Vec<Watchpoint> {
+        &mut self.cpu.m.watchpoints
+    }
+
+    /// Pc of the instruction and the access that first hit a watchpoint since the last call
+    pub fn take_watch_hit(&mut self) -> Option<(u16, Access)> {
+        self.watch_hit.take()
+    }
+
+    /// Reads memory like the cpu would but without spending any cycles
+    pub fn read(&self, address: u16) -> u8 {
+        self.cpu.m.b(address)
+    }
+
+    /// Writes memory like the cpu would, so writes to rom still switch banks
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.cpu.m.wb(address, value)
+    }
+
+    /// Rom bank the cartridge has mapped at an address in 0x0000..=0x7FFF
+    pub fn rom_bank(&self, address: u16) -> usize {
+        self.cpu.m.cart.rom_bank(address)
+    }
+
+    pub fn disasm(&self, address: u16) -> Instruction {
+        decode(address, |a| self.cpu.m.b(a))
+    }
+
     pub(crate) fn print(&self) -> String {
         format!(
             "a: {:#04x}\tf: {:#04x}\tb: {:#04x}\tc: {:#04x}\td: {:#04x}\te: {:#04x}\th: {:#04x}\tl: {:#04x}\tsp: {:#06x}",
@@ -88,3 +246,88 @@ impl Gb {
         )
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{env, fs};
+
+    use super::*;
+
+    /// A system running `code` from 0x0100 in an otherwise empty 32 KiB rom, so nops after it
+    pub(crate) fn test_gb(code: &[u8]) -> Gb {
+        test_gb_with(&[(0x0100, code)])
+    }
+
+    /// Like test_gb with each piece of code at its own address, e.g. interrupt handlers
+    pub(crate) fn test_gb_with(code: &[(u16, &[u8])]) -> Gb {
+        let mut rom = vec![0; 0x8000];
+        for &(address, code) in code {
+            let address = address as usize;
+            rom[address..address + code.len()].copy_from_slice(code);
+        }
+        let file = env::temp_dir().join(format!(
+            "gb-test-{}-{:?}.gb",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        fs::write(&file, rom).unwrap();
+        let cart = Cartridge::new_from_file(&file);
+        fs::remove_file(&file).unwrap();
+        match cart {
+            Ok(cart) => Gb::new(cart, None),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // ld a, $12; ld [$c000], a
+    const WRITE_C000: [u8; 5] = [0x3E, 0x12, 0xEA, 0x00, 0xC0];
+
+    fn watched_gb() -> Gb {
+        let mut gb = test_gb(&WRITE_C000);
+        gb.watchpoints_mut()
+            .push(Watchpoint::parse("c000").unwrap());
+        gb
+    }
+
+    #[test]
+    fn step_until_watch_stops_after_the_hit() {
+        let mut gb = watched_gb();
+        let (cycles, finished) = gb.step_until_watch(0);
+        assert!(!finished);
+        assert!(cycles < ONE_FRAME_CYCLES);
+        assert_eq!(gb.registers().pc, 0x0105);
+        assert!(matches!(
+            gb.take_watch_hit(),
+            Some((
+                0x0102,
+                Access::Write {
+                    address: 0xC000,
+                    value: 0x12,
+                    ..
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn stopped_frame_finishes_like_step_frame() {
+        let mut gb = watched_gb();
+        let (cycles, _) = gb.step_until_watch(0);
+        gb.take_watch_hit();
+        let (cycles, finished) = gb.step_until_watch(cycles);
+        assert!(finished);
+
+        let mut whole = watched_gb();
+        assert_eq!(whole.step_frame(0), cycles);
+        assert_eq!(whole.save_state(), gb.save_state());
+    }
+
+    #[test]
+    fn untaken_hit_does_not_stop_again() {
+        let mut gb = watched_gb();
+        let (cycles, _) = gb.step_until_watch(0);
+        let (_, finished) = gb.step_until_watch(cycles);
+        assert!(finished);
+        assert_eq!(gb.take_watch_hit().map(|(pc, _)| pc), Some(0x0102));
+    }
+}
@@ -2,7 +2,10 @@ use std::{cmp::Ordering, time::Duration};
 
 use num_enum::{IntoPrimitive, UnsafeFromPrimitive};
 
-use super::cpu;
+use super::{
+    cpu,
+    state::{StateError, StateReader, StateWriter},
+};
 
 pub const ONE_FRAME_CYCLES: u32 = 70224;
 pub const ONE_FRAME_DURATION: Duration =
@@ -12,6 +15,18 @@ pub const ONE_FRAME_DURATION: Duration =
 pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 
+// Pixels in buf are a shade 0-3 in the low bits and the palette that produced it above that,
+// which lets frontends colorize each layer differently like the CGB does for DMG games
+pub const PIXEL_SHADE: u8 = 0b0011;
+pub const PIXEL_OBP0: u8 = 0b0100;
+pub const PIXEL_OBP1: u8 = 0b1000;
+
+// Tile map information
+pub const TILEMAP_TILES: usize = 32;
+pub const TILEMAP_WIDTH: usize = TILEMAP_TILES * 8;
+pub const TILEMAP_0: u16 = 0x9800;
+pub const TILEMAP_1: u16 = 0x9C00;
+
 // Addresses
 pub const LCDC: u16 = 0xFF40;
 pub const STAT: u16 = 0xFF41;
@@ -67,7 +82,7 @@ enum StatFlag {
 #[allow(dead_code)]
 #[derive(Copy, Clone, Eq, IntoPrimitive, PartialEq)]
 #[repr(u8)]
-enum Attribute {
+pub(crate) enum Attribute {
     Palette = 0b00000111,       // CGB
     VRamBank = 0b00001000,      // CGB
     PaletteNumber = 0b00010000, // No-CGB
@@ -76,7 +91,7 @@ enum Attribute {
     BGandWindowOverObj = 0b10000000,
 }
 
-fn get_attribute(data: u8, attribute: Attribute) -> bool {
+pub(crate) fn get_attribute(data: u8, attribute: Attribute) -> bool {
     data & (attribute as u8) != 0
 }
 
@@ -89,8 +104,17 @@ enum Mode {
     TransferData = 0b11,
 }
 
+/// Layers that can be hidden for debugging, independently of LCDC
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Obj,
+}
+
 const LINE_CYCLES: u32 = 456;
 const MAX_SPRITES_PER_LINE: usize = 10;
+pub const OAM_OBJECTS: usize = 40;
 
 pub struct Ppu {
     m_ram: [u8; PPU_BANK_SIZE], // tile data, tile maps, CGB: 2 x PPU_BANK_SIZE for
@@ -113,8 +137,10 @@ pub struct Ppu {
     mode: Mode,
     window_counter: Option<u8>,
     palette_index: [u8; LCD_WIDTH],
+    line_objects: [(u64, u64); LCD_HEIGHT],
     blank_frame: bool,
     pub enable_background: bool,
+    pub enable_window: bool,
     pub enable_obj: bool,
 }
 
@@ -140,8 +166,10 @@ impl Ppu {
             mode: Mode::VBlank,
             window_counter: None,
             palette_index: [0x00; LCD_WIDTH],
+            line_objects: [(0, 0); LCD_HEIGHT],
             blank_frame: false,
             enable_background: true,
+            enable_window: true,
             enable_obj: true,
         };
         p.reset();
@@ -167,6 +195,7 @@ impl Ppu {
         self.mode = Mode::VBlank;
         self.window_counter = None;
         self.palette_index = [0x00; LCD_WIDTH];
+        self.line_objects = [(0, 0); LCD_HEIGHT];
         self.blank_frame = false;
     }
 
@@ -211,6 +240,68 @@ impl Ppu {
         }
     }
 
+    /// Layer toggles are a frontend setting and stay as they are
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.m_ram);
+        w.bytes(&self.m_oam);
+        for register in [
+            self.m_lcdc,
+            self.m_stat,
+            self.m_scy,
+            self.m_scx,
+            self.m_ly,
+            self.m_lyc,
+            self.m_dma,
+            self.m_bgp,
+            self.m_obp0,
+            self.m_obp1,
+            self.m_wy,
+            self.m_wx,
+        ] {
+            w.u8(register);
+        }
+        for line in self.buf.iter() {
+            w.bytes(line);
+        }
+        w.u32(self.internal_cycles);
+        w.u8(self.mode.into());
+        w.bool(self.window_counter.is_some());
+        w.u8(self.window_counter.unwrap_or(0));
+        w.bool(self.blank_frame);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.m_ram, "vram")?;
+        r.bytes_into(&mut self.m_oam, "oam")?;
+        for register in [
+            &mut self.m_lcdc,
+            &mut self.m_stat,
+            &mut self.m_scy,
+            &mut self.m_scx,
+            &mut self.m_ly,
+            &mut self.m_lyc,
+            &mut self.m_dma,
+            &mut self.m_bgp,
+            &mut self.m_obp0,
+            &mut self.m_obp1,
+            &mut self.m_wy,
+            &mut self.m_wx,
+        ] {
+            *register = r.u8()?;
+        }
+        for line in self.buf.iter_mut() {
+            r.bytes_into(line, "lcd buffer")?;
+        }
+        self.internal_cycles = r.u32()?;
+        self.mode = unsafe { Mode::from_unchecked(r.u8()? & 0b11) };
+        let window_active = r.bool()?;
+        let window_counter = r.u8()?;
+        self.window_counter = window_active.then_some(window_counter);
+        self.blank_frame = r.bool()?;
+        self.line_objects = [(0, 0); LCD_HEIGHT];
+        Ok(())
+    }
+
     pub fn step(&mut self, mut cycles: u32) -> (bool, bool) {
         let (mut intf_vblank, mut intf_lcdstat) = (false, false);
         if self.get_lcdc_flag(LcdcFlag::LCDEnable) {
@@ -258,28 +349,148 @@ impl Ppu {
 
     fn draw_line(&mut self) {
         self.buf[self.m_ly as usize] = [0x00; LCD_WIDTH];
+        self.line_objects[self.m_ly as usize] = (0, 0);
         if !self.blank_frame {
-            if self.enable_background {
+            if self.enable_background || self.enable_window {
                 self.render_bg_line();
+            } else {
+                // Nothing drawn for objects to hide behind
+                self.palette_index = [0x00; LCD_WIDTH];
             }
-            if self.enable_obj {
-                self.render_obj_line();
-            }
+            // The OAM scan runs with the layer hidden too so the inspector still shows it
+            self.render_obj_line();
         }
     }
 
+    /// Toggles a debug layer, returning whether it is now shown
+    pub fn toggle_layer(&mut self, layer: Layer) -> bool {
+        let enable = match layer {
+            Layer::Background => &mut self.enable_background,
+            Layer::Window => &mut self.enable_window,
+            Layer::Obj => &mut self.enable_obj,
+        };
+        *enable = !*enable;
+        *enable
+    }
+
     fn bg_map_base(&self, window: bool) -> u16 {
         if window {
             if self.get_lcdc_flag(LcdcFlag::WindowTileArea) {
-                0x9C00
+                TILEMAP_1
             } else {
-                0x9800
+                TILEMAP_0
             }
         } else if self.get_lcdc_flag(LcdcFlag::BgTileMapArea) {
-            0x9C00
+            TILEMAP_1
+        } else {
+            TILEMAP_0
+        }
+    }
+
+    /// Address of the tile data for a background or window tile index
+    fn bg_tile_addr(&self, tile_index: u8) -> u16 {
+        if self.get_lcdc_flag(LcdcFlag::BgWindowTileDataArea) {
+            0x8000 + (tile_index as u16) * 16
+        } else {
+            0x8800 + (((tile_index as i8) as i16 + 128) as u16) * 16
+        }
+    }
+
+    fn tile_palette_index(&self, tile_addr: u16, tile_offset_x: u16, tile_offset_y: u16) -> u8 {
+        let tile_y_data = [
+            self.m_ram[(tile_addr + tile_offset_y * 2) as usize - 0x8000],
+            self.m_ram[(tile_addr + tile_offset_y * 2 + 1) as usize - 0x8000],
+        ];
+        ((tile_y_data[1] & (0x80 >> tile_offset_x) != 0) as u8) << 1
+            | ((tile_y_data[0] & (0x80 >> tile_offset_x) != 0) as u8)
+    }
+
+    /// Renders a whole 32x32 tile map through BGP, ignoring scrolling and the window
+    pub fn render_tilemap(&self, map_base: u16) -> [[u8; TILEMAP_WIDTH]; TILEMAP_WIDTH] {
+        let mut buf = [[0x00; TILEMAP_WIDTH]; TILEMAP_WIDTH];
+        for (y, line) in buf.iter_mut().enumerate() {
+            for (x, pixel) in line.iter_mut().enumerate() {
+                let (tile_index, _) = self.tilemap_entry(map_base, (x / 8) as u8, (y / 8) as u8);
+                let tile_addr = self.bg_tile_addr(tile_index);
+                let palette_index =
+                    self.tile_palette_index(tile_addr, (x % 8) as u16, (y % 8) as u16);
+                *pixel = (self.m_bgp >> (2 * palette_index)) & 0b11;
+            }
+        }
+        buf
+    }
+
+    /// Tile index and attributes of a tile map entry.
+    /// Attributes live in CGB VRAM bank 1, so they always read as 0 for now
+    pub fn tilemap_entry(&self, map_base: u16, tile_x: u8, tile_y: u8) -> (u8, u8) {
+        let tilemap_addr = map_base + tile_x as u16 + (tile_y as u16 * TILEMAP_TILES as u16);
+        (self.m_ram[tilemap_addr as usize - 0x8000], 0x00)
+    }
+
+    /// Tile map used by the background and the window respectively
+    pub fn tilemaps(&self) -> (u16, u16) {
+        (self.bg_map_base(false), self.bg_map_base(true))
+    }
+
+    /// (SCX, SCY)
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.m_scx, self.m_scy)
+    }
+
+    /// (WX, WY), only meaningful if window_enabled
+    pub fn window_position(&self) -> (u8, u8) {
+        (self.m_wx, self.m_wy)
+    }
+
+    pub fn window_enabled(&self) -> bool {
+        self.get_lcdc_flag(LcdcFlag::BgWindowEnable) && self.get_lcdc_flag(LcdcFlag::WindowEnable)
+    }
+
+    /// Raw (Y, X, tile index, attributes) of an OAM entry
+    pub fn oam_entry(&self, obj_num: usize) -> (u8, u8, u8, u8) {
+        let obj_index = obj_num * 4;
+        (
+            self.m_oam[obj_index],
+            self.m_oam[obj_index + 1],
+            self.m_oam[obj_index + 2],
+            self.m_oam[obj_index + 3],
+        )
+    }
+
+    pub fn tall_objects(&self) -> bool {
+        self.get_lcdc_flag(LcdcFlag::ObjSize)
+    }
+
+    /// Bitmasks of the OAM entries the last scan of a line selected, and the ones it dropped over the 10 per line limit
+    pub fn line_objects(&self, ly: usize) -> (u64, u64) {
+        self.line_objects[ly]
+    }
+
+    /// Renders an object unflipped through its palette, None is transparent.
+    /// The bottom half is only used for 8x16 objects
+    pub fn render_obj(&self, obj_num: usize) -> [[Option<u8>; 8]; 16] {
+        let (_, _, tile_index, tile_attributes) = self.oam_entry(obj_num);
+        let (tile_index, obj_height) = if self.tall_objects() {
+            (tile_index & 0b11111110, 16)
         } else {
-            0x9800
+            (tile_index, 8)
+        };
+        let palette = if get_attribute(tile_attributes, Attribute::PaletteNumber) {
+            self.m_obp1
+        } else {
+            self.m_obp0
+        };
+        let tile_addr = 0x8000 + (tile_index as u16) * 16;
+        let mut buf = [[None; 8]; 16];
+        for (y, line) in buf.iter_mut().enumerate().take(obj_height) {
+            for (x, pixel) in line.iter_mut().enumerate() {
+                let palette_index = self.tile_palette_index(tile_addr, x as u16, y as u16);
+                if palette_index != 0 {
+                    *pixel = Some((palette >> (2 * palette_index)) & 0b11);
+                }
+            }
         }
+        buf
     }
 
     fn render_bg_line(&mut self) {
@@ -300,12 +511,15 @@ impl Ppu {
         };
         let background_y = self.m_scy.wrapping_add(self.m_ly);
 
-        let tiledata_unsigned = self.get_lcdc_flag(LcdcFlag::BgWindowTileDataArea);
-        let tiledata_base = if tiledata_unsigned { 0x8000 } else { 0x8800 };
-
         for x in 0..LCD_WIDTH {
             let window_x = (x as i16) - ((self.m_wx as i16) - 7);
-            let window_visible = render_window && window_x >= 0 && window_y.is_some();
+            // A hidden window shows the background behind it
+            let window_visible =
+                render_window && window_x >= 0 && window_y.is_some() && self.enable_window;
+            if !window_visible && !self.enable_background {
+                self.palette_index[x] = 0;
+                continue;
+            }
             let (full_x, full_y) = if window_visible {
                 (window_x as u16, window_y.unwrap())
             } else {
@@ -318,20 +532,9 @@ impl Ppu {
 
             let tilemap_addr = background_map_base + tile_x + (tile_y * 32);
             let tile_index = self.m_ram[tilemap_addr as usize - 0x8000];
-            let tile_offset = if tiledata_unsigned {
-                tile_index as i16 as u16
-            } else {
-                ((tile_index as i8) as i16 + 128) as u16
-            } * 16;
-            let tile_addr = tiledata_base + tile_offset;
+            let tile_addr = self.bg_tile_addr(tile_index);
 
-            let tile_y_data = [
-                self.m_ram[(tile_addr + tile_offset_y * 2) as usize - 0x8000],
-                self.m_ram[(tile_addr + tile_offset_y * 2 + 1) as usize - 0x8000],
-            ];
-
-            let palette_index = ((tile_y_data[1] & (0x80 >> tile_offset_x) != 0) as u8) << 1
-                | ((tile_y_data[0] & (0x80 >> tile_offset_x) != 0) as u8);
+            let palette_index = self.tile_palette_index(tile_addr, tile_offset_x, tile_offset_y);
             self.palette_index[x] = palette_index & 0b11;
 
             // Trivial mapping for now, but that's because my representation is exactly the same
@@ -349,7 +552,8 @@ impl Ppu {
 
         let mut sprite_count = 0;
         let mut sprite_buf = [(0, 0, 0, 0); MAX_SPRITES_PER_LINE];
-        for obj_num in 0..40 {
+        let (mut selected, mut dropped) = (0u64, 0u64);
+        for obj_num in 0..OAM_OBJECTS {
             let obj_index = obj_num * 4;
             let (y, x, tile_index, tile_attributes) = (
                 self.m_oam[obj_index].wrapping_sub(16),
@@ -367,11 +571,17 @@ impl Ppu {
                 continue;
             }
             // Only render 10 sprites, does not check X coordinate
-            sprite_buf[sprite_count] = (y, x, tile_index, tile_attributes);
-            sprite_count += 1;
             if sprite_count == MAX_SPRITES_PER_LINE {
-                break;
+                dropped |= 1 << obj_num;
+                continue;
             }
+            sprite_buf[sprite_count] = (y, x, tile_index, tile_attributes);
+            sprite_count += 1;
+            selected |= 1 << obj_num;
+        }
+        self.line_objects[self.m_ly as usize] = (selected, dropped);
+        if !self.enable_obj {
+            return;
         }
 
         sprite_buf[..sprite_count].sort_unstable_by(render_sort);
@@ -402,11 +612,12 @@ impl Ppu {
                 self.m_ram[(tile_addr + tile_offset_y * 2 + 1) as usize - 0x8000],
             ];
 
-            let palette = if get_attribute(tile_attributes, Attribute::PaletteNumber) {
-                self.m_obp1
-            } else {
-                self.m_obp0
-            };
+            let (palette, pixel_palette) =
+                if get_attribute(tile_attributes, Attribute::PaletteNumber) {
+                    (self.m_obp1, PIXEL_OBP1)
+                } else {
+                    (self.m_obp0, PIXEL_OBP0)
+                };
 
             for rel_x in 0..8 {
                 let x_pixel = x.wrapping_add(rel_x) as usize;
@@ -432,7 +643,7 @@ impl Ppu {
                 }
 
                 let color = (palette >> (2 * palette_index)) & 0b11;
-                self.buf[self.m_ly as usize][x_pixel] = color;
+                self.buf[self.m_ly as usize][x_pixel] = color | pixel_palette;
             }
         }
     }
@@ -1,4 +1,7 @@
-use super::cpu::HZ;
+use super::{
+    cpu::HZ,
+    state::{StateError, StateReader, StateWriter},
+};
 
 pub const SB: u16 = 0xFF01;
 pub const SC: u16 = 0xFF02;
@@ -40,6 +43,21 @@ impl Serial {
         self.internal_timer = 0;
     }
 
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.m_data);
+        w.u8(self.m_control);
+        w.bool(self.in_transfer);
+        w.u32(self.internal_timer);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.m_data = r.u8()?;
+        self.m_control = r.u8()?;
+        self.in_transfer = r.bool()?;
+        self.internal_timer = r.u32()?;
+        Ok(())
+    }
+
     pub fn step(&mut self, cycles: u32) -> bool {
         if self.in_transfer {
             self.internal_timer += cycles;
@@ -0,0 +1,182 @@
+use std::error::Error;
+
+use super::cart::info::CartridgeInfo;
+
+const STATE_MAGIC: &[u8; 4] = b"GBST";
+/// Bump whenever a component changes what it writes, old states are rejected instead of misread
+pub const STATE_VERSION: u32 = 5;
+
+#[derive(Debug)]
+pub enum StateError {
+    FileError(Box<dyn Error>),
+    NotAState,
+    Version(u32),
+    WrongRom { state: String, cart: String },
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::FileError(e) => write!(f, "File Error: {}!", e),
+            StateError::NotAState => write!(f, "Not a save state!"),
+            StateError::Version(v) => write!(
+                f,
+                "Save state version {} is not supported, expected version {}!",
+                v, STATE_VERSION
+            ),
+            StateError::WrongRom { state, cart } => write!(
+                f,
+                "Save state belongs to {:?}, but the loaded cartridge is {:?}!",
+                state, cart
+            ),
+            StateError::Truncated => write!(f, "Save state is truncated!"),
+            StateError::Invalid(what) => write!(f, "Save state has an invalid {}!", what),
+        }
+    }
+}
+
+/// Little endian writer the components serialize themselves into, in a fixed order
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Length prefixed bytes
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Reads bytes into a buffer that must have exactly the saved length
+    pub fn bytes_into(&mut self, buf: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buf.len() {
+            return Err(StateError::Invalid(what));
+        }
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn str(&mut self) -> Result<String, StateError> {
+        Ok(String::from_utf8_lossy(self.bytes()?).into_owned())
+    }
+
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("length"))
+        }
+    }
+}
+
+pub fn write_header(w: &mut StateWriter, info: &CartridgeInfo) {
+    w.bytes.extend_from_slice(STATE_MAGIC);
+    w.u32(STATE_VERSION);
+    w.str(&info.title);
+    w.u8(info.header_checksum);
+    w.u16(info.global_checksum);
+}
+
+/// Checks the state was made by this version with the same cartridge
+pub fn read_header(r: &mut StateReader, info: &CartridgeInfo) -> Result<(), StateError> {
+    if r.take(STATE_MAGIC.len()).ok() != Some(STATE_MAGIC) {
+        return Err(StateError::NotAState);
+    }
+    let version = r.u32()?;
+    if version != STATE_VERSION {
+        return Err(StateError::Version(version));
+    }
+    let title = r.str()?;
+    let header_checksum = r.u8()?;
+    let global_checksum = r.u16()?;
+    if title != info.title
+        || header_checksum != info.header_checksum
+        || global_checksum != info.global_checksum
+    {
+        return Err(StateError::WrongRom {
+            state: title,
+            cart: info.title.clone(),
+        });
+    }
+    Ok(())
+}
@@ -1,109 +1,264 @@
+use super::state::{StateError, StateReader, StateWriter};
+
 pub const DIV: u16 = 0xFF04;
 pub const TIMA: u16 = 0xFF05;
 pub const TMA: u16 = 0xFF06;
 pub const TAC: u16 = 0xFF07;
 
 pub struct Timer {
-    m_div: u8,
+    /// Internal counter incremented every T-cycle, DIV is its upper byte
+    counter: u16,
     m_tima: u8,
     m_tma: u8,
     m_tac: u8,
-    div_count: u32,
-    tima_count: u32,
-    enable_tima: bool,
-    tima_step: u32,
+    /// TIMA overflowed on the last M-cycle and reads 0 until it is reloaded on the next one
+    overflow: bool,
+    /// TIMA was reloaded from TMA on this M-cycle, writes to TIMA are ignored and writes to TMA go through
+    reloading: bool,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Self {
-            m_div: 0x18, // CGB: ?
+            counter: 0xABCC, // DMG after the boot rom, CGB: ?
             m_tima: 0x00,
             m_tma: 0x00,
-            m_tac: 0xF8,
-            div_count: 0,
-            tima_count: 0,
-            enable_tima: false,
-            tima_step: 1024,
+            m_tac: 0x00,
+            overflow: false,
+            reloading: false,
         }
     }
 
     pub fn reset(&mut self) {
-        self.m_div = 0x18;
+        self.counter = 0xABCC;
         self.m_tima = 0x00;
         self.m_tma = 0x00;
-        self.m_tac = 0xF8;
-        self.div_count = 0;
-        self.tima_count = 0;
-        self.enable_tima = false;
-        self.tima_step = 1024;
+        self.m_tac = 0x00;
+        self.overflow = false;
+        self.reloading = false;
     }
 
-    pub fn step(&mut self, cycles: u32) -> bool {
-        self.step_div(cycles);
-        self.step_timer(cycles)
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.counter);
+        w.u8(self.m_tima);
+        w.u8(self.m_tma);
+        w.u8(self.m_tac);
+        w.bool(self.overflow);
+        w.bool(self.reloading);
     }
 
-    fn step_div(&mut self, cycles: u32) {
-        self.div_count += cycles;
-        let (div_div_256, div_mod_256) = (self.div_count / 256, self.div_count % 256);
-        self.div_count = div_mod_256;
-        self.m_div = self.m_div.wrapping_add(div_div_256 as u8);
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u16()?;
+        self.m_tima = r.u8()?;
+        self.m_tma = r.u8()?;
+        self.m_tac = r.u8()? & 0b111;
+        self.overflow = r.bool()?;
+        self.reloading = r.bool()?;
+        Ok(())
     }
 
-    fn step_timer(&mut self, cycles: u32) -> bool {
-        if self.enable_tima {
-            self.tima_count += cycles;
-            let (tima_div_step, tima_mod_step) = (
-                self.tima_count / self.tima_step,
-                self.tima_count % self.tima_step,
-            );
-            self.tima_count = tima_mod_step;
+    /// Returns true when the timer interrupt is requested
+    pub fn step(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles / 4 {
+            interrupt |= self.step_m_cycle();
+        }
+        interrupt
+    }
 
-            if (self.m_tima as u32 + tima_div_step) >= 0x100 {
-                self.m_tima = self.m_tma + (self.m_tima as u32 + tima_div_step - 0xFF) as u8;
+    fn step_m_cycle(&mut self) -> bool {
+        self.reloading = false;
+        let interrupt = self.overflow;
+        if self.overflow {
+            self.overflow = false;
+            self.m_tima = self.m_tma;
+            self.reloading = true;
+        }
+        self.set_counter(self.counter.wrapping_add(4));
+        interrupt
+    }
 
-                return true;
-            } else if tima_div_step >= 1 {
-                self.m_tima += tima_div_step as u8;
-            }
+    /// Enable bit of TAC and'ed with the counter bit selected by TAC, TIMA counts its falling edges
+    fn signal(&self) -> bool {
+        let bit = match self.m_tac & 0b011 {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            3 => 7, // 16384 Hz
+            _ => unreachable!(),
+        };
+        self.m_tac & 0b100 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    /// Also used for writes to DIV and TAC, which can cause a falling edge as well
+    fn set_counter(&mut self, counter: u16) {
+        let old = self.signal();
+        self.counter = counter;
+        if old && !self.signal() {
+            self.increment();
         }
-        false
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.m_tima.overflowing_add(1);
+        self.m_tima = tima;
+        self.overflow = overflow;
     }
 
     pub fn b(&self, address: u16) -> u8 {
         match address {
-            DIV => self.m_div,
+            DIV => (self.counter >> 8) as u8,
             TIMA => self.m_tima,
             TMA => self.m_tma,
-            TAC => self.m_tac,
+            TAC => 0xF8 | self.m_tac,
             _ => unreachable!(),
         }
     }
 
     pub fn wb(&mut self, address: u16, value: u8) {
         match address {
-            DIV => {
-                self.m_div = 0x00;
-                self.div_count = 0;
-                self.tima_count = 0; // In the hardware div and tima are the same counter, so they are both reset here
+            DIV => self.set_counter(0),
+            TIMA => {
+                // Writing while TIMA reads 0 cancels the reload and the interrupt
+                if !self.reloading {
+                    self.m_tima = value;
+                    self.overflow = false;
+                }
+            }
+            TMA => {
+                self.m_tma = value;
+                if self.reloading {
+                    self.m_tima = value;
+                }
             }
-            TIMA => self.m_tima = value,
-            TMA => self.m_tma = value,
             TAC => {
-                self.m_tac = 0xF8 & value;
-                self.enable_tima = value & 0b100 != 0;
-                self.tima_step = match value & 0b011 {
-                    0 => 1024,
-                    1 => 16,
-                    2 => 64,
-                    3 => 256,
-                    _ => unreachable!(),
-                };
-                self.m_tima = self.m_tma;
-                //self.tima_count = 0;
+                let old = self.signal();
+                self.m_tac = value & 0b111;
+                if old && !self.signal() {
+                    self.increment();
+                }
             }
             _ => unreachable!(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A timer with the counter at 0 and TAC set
+    fn timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.wb(DIV, 0);
+        timer.wb(TAC, tac);
+        timer
+    }
+
+    /// A timer whose TIMA overflowed on the last M-cycle, reloading from TMA 0x80
+    fn overflowed() -> Timer {
+        let mut timer = timer(0b101);
+        timer.wb(TMA, 0x80);
+        timer.wb(TIMA, 0xFF);
+        assert!(!timer.step(16));
+        assert_eq!(timer.b(TIMA), 0x00);
+        timer
+    }
+
+    #[test]
+    fn tima_counts_falling_edges() {
+        // T-cycles between increments for each clock select
+        for (select, period) in [(0b00, 1024), (0b01, 16), (0b10, 64), (0b11, 256)] {
+            let mut timer = timer(0b100 | select);
+            timer.step(period - 4);
+            assert_eq!(timer.b(TIMA), 0, "TAC {:#05b}", select);
+            timer.step(4);
+            assert_eq!(timer.b(TIMA), 1, "TAC {:#05b}", select);
+            timer.step(period * 3);
+            assert_eq!(timer.b(TIMA), 4, "TAC {:#05b}", select);
+        }
+    }
+
+    #[test]
+    fn disabled_timer_does_not_count() {
+        let mut timer = timer(0b001);
+        timer.step(1024);
+        assert_eq!(timer.b(TIMA), 0);
+        assert_eq!(timer.b(DIV), 4);
+    }
+
+    #[test]
+    fn div_write_glitch() {
+        let mut timer = timer(0b101);
+        // Bit 3 of the counter is set, resetting it is a falling edge
+        timer.step(8);
+        timer.wb(DIV, 0xAB);
+        assert_eq!(timer.b(TIMA), 1);
+        assert_eq!(timer.b(DIV), 0);
+        // With the bit clear nothing happens
+        timer.step(4);
+        timer.wb(DIV, 0);
+        assert_eq!(timer.b(TIMA), 1);
+    }
+
+    #[test]
+    fn tac_change_glitch() {
+        let mut timer = timer(0b101);
+        timer.step(8);
+        // Disabling the timer while the selected bit is set
+        timer.wb(TAC, 0b001);
+        assert_eq!(timer.b(TIMA), 1);
+        // Switching from a set bit to a clear one
+        timer.wb(TAC, 0b101);
+        timer.wb(TAC, 0b100);
+        assert_eq!(timer.b(TIMA), 2);
+        // Disabling with a clear bit or switching to a set bit does nothing
+        timer.wb(TAC, 0b000);
+        assert_eq!(timer.b(TAC), 0xF8);
+        timer.wb(TAC, 0b101);
+        assert_eq!(timer.b(TIMA), 2);
+    }
+
+    #[test]
+    fn reload_is_delayed_one_m_cycle() {
+        let mut timer = overflowed();
+        // The interrupt is raised with the reload
+        assert!(timer.step(4));
+        assert_eq!(timer.b(TIMA), 0x80);
+        assert!(!timer.step(4));
+        assert_eq!(timer.b(TIMA), 0x80);
+    }
+
+    #[test]
+    fn tima_write_cancels_overflow() {
+        let mut timer = overflowed();
+        timer.wb(TIMA, 0x42);
+        assert!(!timer.step(4));
+        assert_eq!(timer.b(TIMA), 0x42);
+    }
+
+    #[test]
+    fn tima_write_ignored_while_reloading() {
+        let mut timer = overflowed();
+        assert!(timer.step(4));
+        timer.wb(TIMA, 0x42);
+        assert_eq!(timer.b(TIMA), 0x80);
+        // Only for that one M-cycle
+        timer.step(4);
+        timer.wb(TIMA, 0x42);
+        assert_eq!(timer.b(TIMA), 0x42);
+    }
+
+    #[test]
+    fn tma_write_while_reloading_sets_tima() {
+        let mut timer = overflowed();
+        assert!(timer.step(4));
+        timer.wb(TMA, 0x33);
+        assert_eq!(timer.b(TIMA), 0x33);
+        assert_eq!(timer.b(TMA), 0x33);
+        // Afterwards TMA is only used for the next reload
+        timer.step(4);
+        timer.wb(TMA, 0x44);
+        assert_eq!(timer.b(TIMA), 0x33);
+    }
+}
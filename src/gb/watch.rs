@@ -0,0 +1,111 @@
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchCondition {
+    Read,
+    Write,
+    /// A write of a different value than the one read back before it
+    Change,
+    /// A write of this value
+    Value(u8),
+}
+
+/// Watches an inclusive range of addresses for cpu accesses
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub condition: WatchCondition,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read { address: u16, value: u8 },
+    Write { address: u16, old: u8, value: u8 },
+}
+
+impl Watchpoint {
+    /// An address or range like c000 or c000-c0ff, optionally followed by :r, :w (the default), :c or :=VALUE
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let hex = |n: &str| {
+            let n = n
+                .strip_prefix('$')
+                .or_else(|| n.strip_prefix("0x"))
+                .unwrap_or(n);
+            u16::from_str_radix(n, 16).map_err(|_| format!("invalid address: {}", n))
+        };
+        let (range, condition) = s.split_once(':').unwrap_or((s, "w"));
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (hex(start)?, hex(end)?),
+            None => (hex(range)?, hex(range)?),
+        };
+        if start > end {
+            return Err("the start of the range has to come before its end".to_string());
+        }
+        let condition = match condition {
+            "r" => WatchCondition::Read,
+            "w" => WatchCondition::Write,
+            "c" => WatchCondition::Change,
+            _ => match condition.strip_prefix('=').map(hex) {
+                Some(Ok(value)) if value <= 0xFF => WatchCondition::Value(value as u8),
+                _ => return Err(format!("expected r, w, c or =VALUE, not {}", condition)),
+            },
+        };
+        Ok(Watchpoint {
+            start,
+            end,
+            condition,
+        })
+    }
+
+    pub fn hit(&self, access: Access) -> bool {
+        let address = match access {
+            Access::Read { address, .. } | Access::Write { address, .. } => address,
+        };
+        if !(self.start..=self.end).contains(&address) {
+            return false;
+        }
+        match (self.condition, access) {
+            (WatchCondition::Read, Access::Read { .. }) => true,
+            (WatchCondition::Write, Access::Write { .. }) => true,
+            (WatchCondition::Change, Access::Write { old, value, .. }) => old != value,
+            (WatchCondition::Value(v), Access::Write { value, .. }) => value == v,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            write!(f, "{:04x}", self.start)?;
+        } else {
+            write!(f, "{:04x}-{:04x}", self.start, self.end)?;
+        }
+        match self.condition {
+            WatchCondition::Read => write!(f, ":r"),
+            WatchCondition::Write => write!(f, ":w"),
+            WatchCondition::Change => write!(f, ":c"),
+            WatchCondition::Value(v) => write!(f, ":={:02x}", v),
+        }
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read { address, value } => {
+                write!(f, "read {:#04x} from {:#06x}", value, address)
+            }
+            Access::Write {
+                address,
+                old,
+                value,
+            } => write!(
+                f,
+                "wrote {:#04x} to {:#06x} (was {:#04x})",
+                value, address, old
+            ),
+        }
+    }
+}
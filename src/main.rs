@@ -1,24 +1,65 @@
 #![allow(clippy::new_without_default)]
 
-use std::{path::PathBuf, process::exit};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+};
 
+use bindings::Bindings;
 use clap::Parser;
+use debugger::run_debugger;
+use disasm::{bank_count, parse_banks, run_disasm};
 use gb::bios::Bios;
 use gb::cart::Cartridge;
+use gb::joypad::{JoypadOptions, OppositePolicy};
+use gb::watch::Watchpoint;
 use gb::Gb;
+use movie::{Movie, MoviePlayer};
+use palette::Palette;
+use recorder::{RecordFormat, Recorder};
+use rewind::Rewind;
+use savestate::StateSlots;
+use screenshot::{save_png, screenshot_path};
+use thread::{Speed, SystemOptions};
 use trace::run_trace;
-use window::launch_window;
+use window::{launch_window, WindowOptions};
 
+mod bindings;
+mod debug;
+mod debugger;
+mod disasm;
+#[cfg(feature = "gamepad")]
+mod gamepad;
 mod gb;
+mod movie;
+mod palette;
+mod recorder;
+mod rewind;
+mod savestate;
+mod screenshot;
 mod thread;
 mod trace;
 pub mod window;
 
 #[derive(Parser)]
 enum Command {
-    Emu(EmuArgs),
+    Emu(Box<EmuArgs>),
     Trace(TraceArgs),
     CartInfo(CartridgeArgs),
+    Screenshot(ScreenshotArgs),
+    Record(RecordArgs),
+    Disasm(DisasmArgs),
+    Debug(DebugArgs),
+}
+
+#[derive(Parser)]
+struct PaletteArgs {
+    /// TOML file with extra palettes, added after the built in ones
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    palettes: Option<std::path::PathBuf>,
+    /// Name of the palette to use
+    #[clap(long)]
+    palette: Option<String>,
 }
 
 #[derive(Parser)]
@@ -29,6 +70,66 @@ struct EmuArgs {
     bios: Option<std::path::PathBuf>,
     #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
     genie: Option<std::path::PathBuf>,
+    #[clap(flatten)]
+    palette: PaletteArgs,
+    /// Directory screenshots and recordings are saved to
+    #[clap(long, value_hint = clap::ValueHint::DirPath, default_value = ".")]
+    screenshot_dir: std::path::PathBuf,
+    /// Integer upscaling of screenshots and recordings
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    screenshot_scale: u8,
+    /// Record from power on to this .y4m, .avi or .png file
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    record: Option<std::path::PathBuf>,
+    /// Format of recordings started with F10
+    #[clap(long, default_value = "y4m", value_parser = ["y4m", "avi", "png"])]
+    record_format: String,
+    /// Directory save states are kept in, defaults to the directory of the cartridge
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    state_dir: Option<std::path::PathBuf>,
+    /// Frames between rewind snapshots
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    rewind_interval: u32,
+    /// Memory kept for rewinding in MiB, 0 disables it
+    #[clap(long, default_value_t = 32)]
+    rewind_memory: usize,
+    /// Speed multiplier from 0.25 to 8, or "uncapped"
+    #[clap(long, default_value = "1", value_parser = Speed::parse)]
+    speed: Speed,
+    /// Speed while fast forwarding
+    #[clap(long, default_value = "uncapped", value_parser = Speed::parse)]
+    fast_forward_speed: Speed,
+    /// Only show every Nth frame while fast forwarding
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    frameskip: u32,
+    /// Movie to play back, the joypad is ignored until it is finished
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    movie: Option<std::path::PathBuf>,
+    /// Record a movie from power on to this file
+    #[clap(long, value_hint = clap::ValueHint::FilePath, conflicts_with = "movie")]
+    record_movie: Option<std::path::PathBuf>,
+    /// Frames turbo buttons stay pressed and then released
+    #[clap(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..))]
+    turbo_frames: u8,
+    /// What holding opposite directions does: allow both, block the newer one or let the last one win
+    #[clap(long, default_value = "allow", value_parser = OppositePolicy::parse)]
+    opposite_directions: OppositePolicy,
+    /// TOML file with key bindings, defaults to bindings.toml in the config directory
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    bindings: Option<std::path::PathBuf>,
+    /// Bind a key on top of the bindings file, e.g. --bind pause=Space
+    #[clap(long, value_name = "ACTION=KEY")]
+    bind: Vec<String>,
+    /// Bind controller inputs on top of the bindings file, e.g. --bind-pad a=South,North
+    #[clap(long, value_name = "ACTION=INPUTS")]
+    bind_pad: Vec<String>,
+    /// Pause when the cpu accesses memory, e.g. --watch c000-c0ff:w, see the README for the conditions
+    #[clap(long, value_name = "RANGE[:COND]", value_parser = Watchpoint::parse)]
+    watch: Vec<Watchpoint>,
+    /// How far controller sticks have to be pushed to count, from 0 to 1
+    #[cfg(feature = "gamepad")]
+    #[clap(long, default_value_t = 0.5, value_parser = gamepad::parse_dead_zone)]
+    dead_zone: f32,
 }
 
 #[derive(Parser)]
@@ -41,6 +142,69 @@ struct TraceArgs {
     verbose: bool,
 }
 
+#[derive(Parser)]
+struct ScreenshotArgs {
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    cart: std::path::PathBuf,
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    bios: Option<std::path::PathBuf>,
+    /// Frames to run before taking the screenshot, defaults to 60 or the length of the movie
+    #[clap(long)]
+    frames: Option<u32>,
+    /// Movie to play back while running
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    movie: Option<std::path::PathBuf>,
+    /// Integer upscaling of the screenshot
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    scale: u8,
+    /// Defaults to a file named after the cartridge title and the time in the current directory
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    output: Option<std::path::PathBuf>,
+    #[clap(flatten)]
+    palette: PaletteArgs,
+}
+
+#[derive(Parser)]
+struct RecordArgs {
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    cart: std::path::PathBuf,
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    bios: Option<std::path::PathBuf>,
+    /// Frames to record, defaults to the length of the movie
+    #[clap(long, required_unless_present = "movie")]
+    frames: Option<u32>,
+    /// Movie to play back while recording
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    movie: Option<std::path::PathBuf>,
+    /// Integer upscaling of the video
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    scale: u8,
+    /// A .y4m or .avi video, or a .png name that numbered frames are saved next to
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    output: std::path::PathBuf,
+    #[clap(flatten)]
+    palette: PaletteArgs,
+}
+
+#[derive(Parser)]
+struct DisasmArgs {
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    cart: std::path::PathBuf,
+    /// Rom bank to dump or an inclusive range of them, e.g. 1 or 0-3
+    #[clap(long, default_value = "0", value_parser = parse_banks)]
+    banks: (usize, usize),
+}
+
+#[derive(Parser)]
+struct DebugArgs {
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    cart: std::path::PathBuf,
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    bios: Option<std::path::PathBuf>,
+    #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
+    genie: Option<std::path::PathBuf>,
+}
+
 #[derive(Parser)]
 struct CartridgeArgs {
     #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
@@ -76,16 +240,209 @@ fn make_gb(cart: PathBuf, bios: Option<PathBuf>, genie: Option<PathBuf>) -> Gb {
     Gb::new(cart, bios)
 }
 
+fn make_palettes(args: PaletteArgs) -> (Vec<Palette>, usize) {
+    let mut palettes = palette::builtin();
+    if let Some(file) = args.palettes {
+        match palette::load_from_file(&file) {
+            Ok(custom) => palettes.extend(custom),
+            Err(e) => {
+                eprintln!("Error reading palettes:");
+                eprintln!("{}", e);
+                exit(-3)
+            }
+        }
+    }
+    let index = match args.palette {
+        Some(name) => match palettes.iter().position(|p| p.name == name) {
+            Some(i) => i,
+            None => {
+                eprintln!("Unknown palette: {}", name);
+                exit(-3)
+            }
+        },
+        None => 0,
+    };
+    (palettes, index)
+}
+
+fn load_movie(file: &Path) -> Movie {
+    match Movie::load_from_file(file) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Error reading movie:");
+            eprintln!("{}", e);
+            exit(-6)
+        }
+    }
+}
+
+/// Puts the system at the start of the movie, returns the cycles to carry into the first frame
+fn start_movie(file: &Path, gb: &mut Gb) -> (MoviePlayer, u32) {
+    match MoviePlayer::start(load_movie(file), gb) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("Error playing movie:");
+            eprintln!("{}", e);
+            exit(-6)
+        }
+    }
+}
+
+/// Runs a frame with the inputs of the movie if one is playing
+fn step_frame(gb: &mut Gb, player: Option<&mut MoviePlayer>, cycles: u32) -> u32 {
+    match player {
+        Some(p) => p.step_frame(gb, cycles).0,
+        None => gb.step_frame(cycles),
+    }
+}
+
+fn check_desync(player: Option<&MoviePlayer>) {
+    if let Some(frame) = player.and_then(|p| p.desync()) {
+        eprintln!("Movie desynced at frame {}!", frame);
+        exit(-6)
+    }
+}
+
 fn main() {
     match Command::parse() {
         Command::Emu(args) => {
-            let gb = make_gb(args.cart, args.bios, args.genie);
-            launch_window(gb);
+            let slots = StateSlots::new(&args.cart, args.state_dir);
+            let movie = args.movie.as_deref().map(load_movie);
+            let bindings =
+                match Bindings::load(args.bindings.as_deref(), &args.bind, &args.bind_pad) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        eprintln!("Error reading key bindings:");
+                        eprintln!("{}", e);
+                        exit(-7)
+                    }
+                };
+            let mut gb = make_gb(args.cart, args.bios, args.genie);
+            gb.set_joypad_options(JoypadOptions {
+                opposite_policy: args.opposite_directions,
+                turbo_frames: args.turbo_frames,
+            });
+            gb.watchpoints_mut().extend(args.watch);
+            let (palettes, palette) = make_palettes(args.palette);
+            launch_window(
+                gb,
+                WindowOptions {
+                    palettes,
+                    palette,
+                    screenshot_dir: args.screenshot_dir.clone(),
+                    screenshot_scale: args.screenshot_scale as usize,
+                    record: args.record,
+                    record_format: RecordFormat::from_extension(&args.record_format).unwrap(),
+                    bindings,
+                    #[cfg(feature = "gamepad")]
+                    dead_zone: args.dead_zone,
+                    system: SystemOptions {
+                        slots,
+                        rewind: Rewind::new(args.rewind_interval, args.rewind_memory * 1024 * 1024),
+                        speed: args.speed,
+                        fast_forward_speed: args.fast_forward_speed,
+                        frameskip: args.frameskip,
+                        movie,
+                        record_movie: args.record_movie,
+                        movie_dir: args.screenshot_dir,
+                    },
+                },
+            );
         }
         Command::Trace(args) => {
             let gb = make_gb(args.cart, None, None);
             run_trace(gb, args.cycles, args.verbose);
         }
+        Command::Screenshot(args) => {
+            let mut gb = make_gb(args.cart, args.bios, None);
+            let (palettes, palette) = make_palettes(args.palette);
+            let (mut player, mut cycles) = match &args.movie {
+                Some(file) => {
+                    let (player, cycles) = start_movie(file, &mut gb);
+                    (Some(player), cycles)
+                }
+                None => (None, 0),
+            };
+            let frames = args
+                .frames
+                .or_else(|| player.as_ref().map(|p| p.frames()))
+                .unwrap_or(60);
+            for _ in 0..frames {
+                cycles = step_frame(&mut gb, player.as_mut(), cycles);
+            }
+            let file = args
+                .output
+                .unwrap_or_else(|| screenshot_path(Path::new("."), &gb.cart_info().title));
+            match save_png(
+                &file,
+                &gb.get_buf(),
+                &palettes[palette],
+                args.scale as usize,
+            ) {
+                Ok(()) => println!("Saved {}", file.display()),
+                Err(e) => {
+                    eprintln!("Error saving screenshot:");
+                    eprintln!("{}", e);
+                    exit(-4)
+                }
+            }
+            check_desync(player.as_ref());
+        }
+        Command::Record(args) => {
+            let mut gb = make_gb(args.cart, args.bios, None);
+            let (palettes, palette) = make_palettes(args.palette);
+            let (mut player, mut cycles) = match &args.movie {
+                Some(file) => {
+                    let (player, cycles) = start_movie(file, &mut gb);
+                    (Some(player), cycles)
+                }
+                None => (None, 0),
+            };
+            // Either frames or movie is required
+            let frames = args
+                .frames
+                .or_else(|| player.as_ref().map(|p| p.frames()))
+                .unwrap();
+            let result = Recorder::new(&args.output, &palettes[palette], args.scale as usize)
+                .and_then(|mut recorder| {
+                    for _ in 0..frames {
+                        cycles = step_frame(&mut gb, player.as_mut(), cycles);
+                        recorder.frame(&gb.get_buf())?;
+                    }
+                    recorder.finish()
+                });
+            match result {
+                Ok(()) => println!("Saved {} ({} frames)", args.output.display(), frames),
+                Err(e) => {
+                    eprintln!("Error recording:");
+                    eprintln!("{}", e);
+                    exit(-5)
+                }
+            }
+            check_desync(player.as_ref());
+        }
+        Command::Disasm(args) => {
+            let rom = match std::fs::read(&args.cart) {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("Error reading cartridge:");
+                    eprintln!("{}", e);
+                    exit(-1)
+                }
+            };
+            let (first, last) = args.banks;
+            let banks = bank_count(&rom);
+            if last >= banks {
+                eprintln!("Error disassembling:");
+                eprintln!("Bank {} is out of range, the rom has {} banks", last, banks);
+                exit(-8)
+            }
+            run_disasm(&rom, first, last);
+        }
+        Command::Debug(args) => {
+            let gb = make_gb(args.cart, args.bios, args.genie);
+            run_debugger(gb);
+        }
         Command::CartInfo(args) => {
             let cart = Cartridge::new_from_file(&args.cart);
             match cart {
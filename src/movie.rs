@@ -0,0 +1,339 @@
+use std::{
+    error::Error,
+    fs::{read, write},
+    path::{Path, PathBuf},
+};
+
+use crate::gb::{
+    joypad::{JoypadOptions, OppositePolicy},
+    state::{StateError, StateReader, StateWriter},
+    Gb,
+};
+
+const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+const MOVIE_VERSION: u32 = 3;
+/// Frames between state hashes
+pub const CHECKPOINT_INTERVAL: u32 = 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    FileError(Box<dyn Error>),
+    NotAMovie,
+    Version(u32),
+    WrongRom {
+        movie: String,
+        cart: String,
+    },
+    /// Whether the movie was recorded with the bios
+    Bios(bool),
+    State(StateError),
+}
+
+impl std::fmt::Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MovieError::FileError(e) => write!(f, "File Error: {}!", e),
+            MovieError::NotAMovie => write!(f, "Not a movie!"),
+            MovieError::Version(v) => write!(
+                f,
+                "Movie version {} is not supported, expected version {}!",
+                v, MOVIE_VERSION
+            ),
+            MovieError::WrongRom { movie, cart } => write!(
+                f,
+                "Movie was recorded with {:?}, but the loaded cartridge is {:?}!",
+                movie, cart
+            ),
+            MovieError::Bios(true) => write!(
+                f,
+                "Movie was recorded with the bios, but the emulator runs without one!"
+            ),
+            MovieError::Bios(false) => write!(
+                f,
+                "Movie was recorded without the bios, but the emulator runs with one!"
+            ),
+            MovieError::State(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+/// Where playback begins
+pub enum MovieStart {
+    PowerOn,
+    /// A save state and the cycles step_frame carried over into the first frame
+    State {
+        state: Vec<u8>,
+        cycles: u32,
+    },
+}
+
+/// Buttons held on every frame and state hashes to notice desyncs
+pub struct Movie {
+    pub title: String,
+    pub header_checksum: u8,
+    /// Booting through the bios takes a different path to the game, so it has to match too
+    pub bios: bool,
+    /// Turbo and the opposite direction policy change what the inputs do, so they are replayed too
+    pub joypad: JoypadOptions,
+    pub start: MovieStart,
+    /// Held buttons in the low byte and turbo buttons in the high byte, see joypad::Button::mask
+    pub inputs: Vec<u16>,
+    /// (frame, state hash) after that many frames ran
+    pub checkpoints: Vec<(u32, u64)>,
+}
+
+impl Movie {
+    pub fn load_from_file(file: &Path) -> Result<Self, MovieError> {
+        let bytes = match read(file) {
+            Ok(b) => b,
+            Err(e) => return Err(MovieError::FileError(Box::new(e))),
+        };
+        let mut r = StateReader::new(&bytes);
+        if r.bytes().ok() != Some(MOVIE_MAGIC) {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = r.u32()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::Version(version));
+        }
+        let title = r.str()?;
+        let header_checksum = r.u8()?;
+        let bios = r.bool()?;
+        let joypad = JoypadOptions {
+            opposite_policy: OppositePolicy::from_u8(r.u8()?)
+                .ok_or(StateError::Invalid("opposite direction policy"))?,
+            turbo_frames: r.u8()?,
+        };
+        let start = if r.bool()? {
+            let state = r.bytes()?.to_vec();
+            let cycles = r.u32()?;
+            MovieStart::State { state, cycles }
+        } else {
+            MovieStart::PowerOn
+        };
+        let mut inputs = vec![];
+        for _ in 0..r.u32()? {
+            inputs.push(r.u16()?);
+        }
+        let mut checkpoints = vec![];
+        for _ in 0..r.u32()? {
+            checkpoints.push((r.u32()?, r.u64()?));
+        }
+        r.finish()?;
+        Ok(Self {
+            title,
+            header_checksum,
+            bios,
+            joypad,
+            start,
+            inputs,
+            checkpoints,
+        })
+    }
+
+    pub fn save_to_file(&self, file: &Path) -> Result<(), MovieError> {
+        let mut w = StateWriter::new();
+        w.bytes(MOVIE_MAGIC);
+        w.u32(MOVIE_VERSION);
+        w.str(&self.title);
+        w.u8(self.header_checksum);
+        w.bool(self.bios);
+        w.u8(self.joypad.opposite_policy as u8);
+        w.u8(self.joypad.turbo_frames);
+        match &self.start {
+            MovieStart::PowerOn => w.bool(false),
+            MovieStart::State { state, cycles } => {
+                w.bool(true);
+                w.bytes(state);
+                w.u32(*cycles);
+            }
+        }
+        w.u32(self.inputs.len() as u32);
+        for input in self.inputs.iter() {
+            w.u16(*input);
+        }
+        w.u32(self.checkpoints.len() as u32);
+        for (frame, hash) in self.checkpoints.iter() {
+            w.u32(*frame);
+            w.u64(*hash);
+        }
+        write(file, w.into_bytes()).map_err(|e| MovieError::FileError(Box::new(e)))
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.inputs.len() as u32
+    }
+}
+
+/// FNV-1a, stable across builds unlike std's hasher
+fn state_hash(gb: &Gb) -> u64 {
+    gb.save_state()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+pub struct MovieRecorder {
+    path: PathBuf,
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Records from power on if `from_power_on`, otherwise embeds the current state
+    pub fn new(path: &Path, gb: &Gb, cycles: u32, from_power_on: bool) -> Self {
+        let start = if from_power_on {
+            MovieStart::PowerOn
+        } else {
+            MovieStart::State {
+                state: gb.save_state(),
+                cycles,
+            }
+        };
+        Self {
+            path: path.to_path_buf(),
+            movie: Movie {
+                title: gb.cart_info().title.clone(),
+                header_checksum: gb.cart_info().header_checksum,
+                bios: gb.uses_bios(),
+                joypad: gb.joypad_options(),
+                start,
+                inputs: vec![],
+                checkpoints: vec![],
+            },
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.movie.frames()
+    }
+
+    /// Holds `buttons` and `turbo` for the frame about to run and records them
+    pub fn start_frame(&mut self, gb: &mut Gb, buttons: u8, turbo: u8) {
+        gb.set_buttons(buttons);
+        gb.set_turbo(turbo);
+        self.movie.inputs.push(buttons as u16 | (turbo as u16) << 8);
+    }
+
+    /// Adds a checkpoint if the frame that just finished is due for one
+    pub fn end_frame(&mut self, gb: &Gb) {
+        let frame = self.movie.frames();
+        if frame.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.movie.checkpoints.push((frame, state_hash(gb)));
+        }
+    }
+
+    pub fn finish(self) -> Result<(), MovieError> {
+        self.movie.save_to_file(&self.path)
+    }
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    /// Joypad options from before the movie, put back by stop
+    joypad: JoypadOptions,
+    frame: u32,
+    checkpoint: usize,
+    desync: Option<u32>,
+}
+
+impl MoviePlayer {
+    /// Checks the movie belongs to the cartridge and puts the system at its start point with the
+    /// joypad options it was recorded with, returns the cycles to carry into the first frame
+    pub fn start(movie: Movie, gb: &mut Gb) -> Result<(Self, u32), MovieError> {
+        let info = gb.cart_info();
+        if movie.title != info.title || movie.header_checksum != info.header_checksum {
+            return Err(MovieError::WrongRom {
+                movie: movie.title,
+                cart: info.title.clone(),
+            });
+        }
+        if movie.bios != gb.uses_bios() {
+            return Err(MovieError::Bios(movie.bios));
+        }
+        let joypad = gb.joypad_options();
+        gb.set_joypad_options(movie.joypad);
+        let cycles = match &movie.start {
+            MovieStart::PowerOn => {
+                gb.reset();
+                0
+            }
+            MovieStart::State { state, cycles } => {
+                gb.load_state(state)?;
+                *cycles
+            }
+        };
+        let player = Self {
+            movie,
+            joypad,
+            frame: 0,
+            checkpoint: 0,
+            desync: None,
+        };
+        Ok((player, cycles))
+    }
+
+    /// Puts back the joypad options from before the movie
+    pub fn stop(self, gb: &mut Gb) {
+        gb.set_joypad_options(self.joypad);
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames()
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.movie.frames()
+    }
+
+    /// The first frame whose state did not match the recording
+    pub fn desync(&self) -> Option<u32> {
+        self.desync
+    }
+
+    /// Runs the next frame of the movie, once it is finished nothing is held anymore.
+    /// Returns the frame if this is where the playback first desynced
+    pub fn step_frame(&mut self, gb: &mut Gb, cycles: u32) -> (u32, Option<u32>) {
+        self.start_frame(gb);
+        let cycles = gb.step_frame(cycles);
+        (cycles, self.end_frame(gb))
+    }
+
+    /// Holds the buttons of the next frame of the movie, nothing once it is finished
+    pub fn start_frame(&mut self, gb: &mut Gb) {
+        let input = self
+            .movie
+            .inputs
+            .get(self.frame as usize)
+            .copied()
+            .unwrap_or(0);
+        gb.set_buttons(input as u8);
+        gb.set_turbo((input >> 8) as u8);
+    }
+
+    /// Checks the frame that just finished against the recording,
+    /// returns it if this is where the playback first desynced
+    pub fn end_frame(&mut self, gb: &Gb) -> Option<u32> {
+        self.frame += 1;
+        if let Some(&(frame, hash)) = self.movie.checkpoints.get(self.checkpoint) {
+            if frame == self.frame {
+                self.checkpoint += 1;
+                if self.desync.is_none() && hash != state_hash(gb) {
+                    self.desync = Some(frame);
+                    return self.desync;
+                }
+            }
+        }
+        None
+    }
+}
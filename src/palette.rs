@@ -0,0 +1,172 @@
+use std::{error::Error, fs::read_to_string, path::Path};
+
+use serde::Deserialize;
+
+use crate::gb::ppu::{PIXEL_OBP0, PIXEL_OBP1, PIXEL_SHADE};
+
+/// RGBA colors for the four shades, lightest first
+pub type Shades = [[u8; 4]; 4];
+
+/// Colors for each palette the PPU can draw through.
+/// Using the same shades for all three gives the plain DMG look
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub bg: Shades,
+    pub obp0: Shades,
+    pub obp1: Shades,
+}
+
+impl Palette {
+    fn uniform(name: &str, shades: Shades) -> Self {
+        Self {
+            name: name.to_string(),
+            bg: shades,
+            obp0: shades,
+            obp1: shades,
+        }
+    }
+
+    /// Color of a pixel from the PPU buffer
+    pub fn color(&self, pixel: u8) -> [u8; 4] {
+        let shade = (pixel & PIXEL_SHADE) as usize;
+        if pixel & PIXEL_OBP1 != 0 {
+            self.obp1[shade]
+        } else if pixel & PIXEL_OBP0 != 0 {
+            self.obp0[shade]
+        } else {
+            self.bg[shade]
+        }
+    }
+}
+
+/// The built in palettes, the first one is the default
+pub fn builtin() -> Vec<Palette> {
+    vec![
+        Palette::uniform(
+            "default",
+            [
+                [255, 239, 206, 0xFF],
+                [222, 148, 74, 0xFF],
+                [173, 41, 33, 0xFF],
+                [49, 24, 82, 0xFF],
+            ],
+        ),
+        Palette::uniform(
+            "dmg",
+            [
+                [0x9B, 0xBC, 0x0F, 0xFF],
+                [0x8B, 0xAC, 0x0F, 0xFF],
+                [0x30, 0x62, 0x30, 0xFF],
+                [0x0F, 0x38, 0x0F, 0xFF],
+            ],
+        ),
+        Palette::uniform(
+            "pocket",
+            [
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0xCC, 0xCC, 0xCC, 0xFF],
+                [0x77, 0x77, 0x77, 0xFF],
+                [0x00, 0x00, 0x00, 0xFF],
+            ],
+        ),
+        // The CGB boot rom palette picked with "right" on the logo
+        Palette {
+            name: "cgb".to_string(),
+            bg: [
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0x7B, 0xFF, 0x31, 0xFF],
+                [0x00, 0x63, 0xC5, 0xFF],
+                [0x00, 0x00, 0x00, 0xFF],
+            ],
+            obp0: [
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0xFF, 0x84, 0x84, 0xFF],
+                [0x94, 0x3A, 0x3A, 0xFF],
+                [0x00, 0x00, 0x00, 0xFF],
+            ],
+            obp1: [
+                [0xFF, 0xFF, 0xFF, 0xFF],
+                [0xFF, 0x84, 0x84, 0xFF],
+                [0x94, 0x3A, 0x3A, 0xFF],
+                [0x00, 0x00, 0x00, 0xFF],
+            ],
+        },
+    ]
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    FileError(Box<dyn Error>),
+    Parse(toml::de::Error),
+    Color(String),
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::FileError(e) => write!(f, "File Error: {}!", e),
+            PaletteError::Parse(e) => write!(f, "Parse Error: {}", e),
+            PaletteError::Color(c) => write!(f, "Invalid color {:?}, expected \"#RRGGBB\"!", c),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PaletteFile {
+    palette: Vec<PaletteEntry>,
+}
+
+/// A palette in a palette file, obp0 and obp1 fall back to bg
+#[derive(Deserialize)]
+struct PaletteEntry {
+    name: String,
+    bg: [String; 4],
+    obp0: Option<[String; 4]>,
+    obp1: Option<[String; 4]>,
+}
+
+fn parse_color(color: &str) -> Result<[u8; 4], PaletteError> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xFF]),
+        _ => Err(PaletteError::Color(color.to_string())),
+    }
+}
+
+fn parse_shades(shades: &[String; 4]) -> Result<Shades, PaletteError> {
+    Ok([
+        parse_color(&shades[0])?,
+        parse_color(&shades[1])?,
+        parse_color(&shades[2])?,
+        parse_color(&shades[3])?,
+    ])
+}
+
+/// Loads palettes from a TOML file made of [[palette]] tables:
+/// ```toml
+/// [[palette]]
+/// name = "custom"
+/// bg = ["#E0F8D0", "#88C070", "#346856", "#081820"]
+/// obp0 = ["#FFFFFF", "#FF8484", "#943A3A", "#000000"] # optional
+/// ```
+pub fn load_from_file(file: &Path) -> Result<Vec<Palette>, PaletteError> {
+    let text = match read_to_string(file) {
+        Ok(t) => t,
+        Err(e) => return Err(PaletteError::FileError(Box::new(e))),
+    };
+    let palette_file: PaletteFile = toml::from_str(&text).map_err(PaletteError::Parse)?;
+    palette_file
+        .palette
+        .iter()
+        .map(|entry| {
+            let bg = parse_shades(&entry.bg)?;
+            Ok(Palette {
+                name: entry.name.clone(),
+                bg,
+                obp0: entry.obp0.as_ref().map_or(Ok(bg), parse_shades)?,
+                obp1: entry.obp1.as_ref().map_or(Ok(bg), parse_shades)?,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,300 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    gb::{
+        cpu::HZ,
+        ppu::{LCD_HEIGHT, LCD_WIDTH, ONE_FRAME_CYCLES},
+    },
+    palette::Palette,
+    screenshot::{save_png, to_rgba, ScreenshotError},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordFormat {
+    /// Uncompressed YUV 4:4:4
+    Y4m,
+    /// Uncompressed 24 bit RGB
+    Avi,
+    /// One numbered PNG per frame
+    Png,
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    FileError(Box<dyn Error>),
+    Screenshot(ScreenshotError),
+    UnknownFormat,
+    TooLarge,
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::FileError(e) => write!(f, "File Error: {}!", e),
+            RecordError::Screenshot(e) => write!(f, "{}", e),
+            RecordError::UnknownFormat => {
+                write!(f, "Unknown recording format, use .y4m, .avi or .png!")
+            }
+            RecordError::TooLarge => write!(f, "Recording is too large for an AVI file!"),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordError {
+    fn from(e: std::io::Error) -> Self {
+        RecordError::FileError(Box::new(e))
+    }
+}
+
+impl RecordFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "y4m" => Some(RecordFormat::Y4m),
+            "avi" => Some(RecordFormat::Avi),
+            "png" => Some(RecordFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self, RecordError> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_extension)
+            .ok_or(RecordError::UnknownFormat)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Y4m => "y4m",
+            RecordFormat::Avi => "avi",
+            RecordFormat::Png => "png",
+        }
+    }
+}
+
+enum Output {
+    Y4m(BufWriter<File>),
+    Avi {
+        writer: BufWriter<File>,
+        frame_offsets: Vec<u32>,
+    },
+    Png,
+}
+
+/// Writes every emulated frame to a video file, independent of how fast frames are displayed.
+/// The frame rate is the exact Game Boy refresh rate of HZ / ONE_FRAME_CYCLES
+pub struct Recorder {
+    path: PathBuf,
+    output: Output,
+    palette: Palette,
+    scale: usize,
+    frames: u32,
+}
+
+// Offsets of the header fields that are only known once recording is done
+const AVI_RIFF_SIZE: u64 = 4;
+const AVI_TOTAL_FRAMES: u64 = 48;
+const AVI_STREAM_LENGTH: u64 = 140;
+const AVI_MOVI_SIZE: u64 = 216;
+const AVI_HEADER_SIZE: u32 = 224;
+
+impl Recorder {
+    pub fn new(path: &Path, palette: &Palette, scale: usize) -> Result<Self, RecordError> {
+        let (width, height) = (LCD_WIDTH * scale, LCD_HEIGHT * scale);
+        let output = match RecordFormat::from_path(path)? {
+            RecordFormat::Y4m => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                    width, height, HZ, ONE_FRAME_CYCLES
+                )?;
+                Output::Y4m(writer)
+            }
+            RecordFormat::Avi => {
+                let mut writer = BufWriter::new(File::create(path)?);
+                write_avi_header(&mut writer, width as u32, height as u32)?;
+                Output::Avi {
+                    writer,
+                    frame_offsets: vec![],
+                }
+            }
+            RecordFormat::Png => Output::Png,
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            output,
+            palette: palette.clone(),
+            scale,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn frame(&mut self, buf: &[[u8; LCD_WIDTH]; LCD_HEIGHT]) -> Result<(), RecordError> {
+        match &mut self.output {
+            Output::Y4m(writer) => {
+                let rgba = to_rgba(buf, &self.palette, self.scale);
+                let pixels = rgba.len() / 4;
+                let mut planes = vec![0; pixels * 3];
+                for (i, c) in rgba.chunks_exact(4).enumerate() {
+                    let (y, u, v) = rgb_to_yuv(c[0], c[1], c[2]);
+                    planes[i] = y;
+                    planes[pixels + i] = u;
+                    planes[2 * pixels + i] = v;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&planes)?;
+            }
+            Output::Avi {
+                writer,
+                frame_offsets,
+            } => {
+                // Bottom up BGR rows
+                let rgba = to_rgba(buf, &self.palette, self.scale);
+                let row_len = LCD_WIDTH * self.scale * 4;
+                let mut data = Vec::with_capacity(rgba.len() / 4 * 3);
+                for row in rgba.chunks_exact(row_len).rev() {
+                    for c in row.chunks_exact(4) {
+                        data.extend_from_slice(&[c[2], c[1], c[0]]);
+                    }
+                }
+                let offset = writer.stream_position()?;
+                if offset + data.len() as u64 + 8 + 16 * (frame_offsets.len() as u64 + 1)
+                    > u32::MAX as u64
+                {
+                    return Err(RecordError::TooLarge);
+                }
+                frame_offsets.push(offset as u32 - AVI_HEADER_SIZE + 4);
+                writer.write_all(b"00db")?;
+                writer.write_all(&(data.len() as u32).to_le_bytes())?;
+                writer.write_all(&data)?;
+            }
+            Output::Png => {
+                let file = numbered_path(&self.path, self.frames);
+                save_png(&file, buf, &self.palette, self.scale).map_err(RecordError::Screenshot)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Flushes the recording, AVI files are unplayable until this is called
+    pub fn finish(self) -> Result<(), RecordError> {
+        match self.output {
+            Output::Y4m(mut writer) => writer.flush()?,
+            Output::Avi {
+                mut writer,
+                frame_offsets,
+            } => {
+                let movi_end = writer.stream_position()?;
+                let frame_size = if frame_offsets.len() > 1 {
+                    frame_offsets[1] - frame_offsets[0] - 8
+                } else {
+                    (movi_end as u32).saturating_sub(AVI_HEADER_SIZE + 8)
+                };
+                writer.write_all(b"idx1")?;
+                writer.write_all(&(frame_offsets.len() as u32 * 16).to_le_bytes())?;
+                for offset in frame_offsets.iter() {
+                    writer.write_all(b"00db")?;
+                    writer.write_all(&0x10u32.to_le_bytes())?; // AVIIF_KEYFRAME
+                    writer.write_all(&offset.to_le_bytes())?;
+                    writer.write_all(&frame_size.to_le_bytes())?;
+                }
+                let file_end = writer.stream_position()?;
+                let frames = frame_offsets.len() as u32;
+                for (position, value) in [
+                    (AVI_RIFF_SIZE, file_end as u32 - 8),
+                    (AVI_TOTAL_FRAMES, frames),
+                    (AVI_STREAM_LENGTH, frames),
+                    (AVI_MOVI_SIZE, movi_end as u32 - AVI_MOVI_SIZE as u32 - 4),
+                ] {
+                    writer.seek(SeekFrom::Start(position))?;
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                writer.flush()?;
+            }
+            Output::Png => (),
+        }
+        Ok(())
+    }
+}
+
+/// "video.png" becomes "video_000042.png"
+fn numbered_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}_{:06}.png", stem, frame))
+}
+
+/// BT.601 limited range, which is what players assume for Y4M
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
+
+/// Writes the RIFF, avih, strh and strf headers and opens the movi list, sizes are patched in by finish
+fn write_avi_header(writer: &mut impl Write, width: u32, height: u32) -> std::io::Result<()> {
+    let frame_size = width * height * 3;
+    let micros_per_frame = (ONE_FRAME_CYCLES as u64 * 1_000_000 / HZ as u64) as u32;
+    let mut header = vec![];
+    let mut chunk = |id: &[u8], values: &[u32]| {
+        header.extend_from_slice(id);
+        for v in values {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+    };
+    chunk(b"RIFF", &[0]);
+    chunk(b"AVI LIST", &[192]);
+    chunk(b"hdrlavih", &[56]);
+    chunk(
+        b"",
+        &[
+            micros_per_frame,
+            frame_size.saturating_mul(HZ / ONE_FRAME_CYCLES + 1),
+            0,
+            0x10, // AVIF_HASINDEX
+            0,    // Total frames
+            0,
+            1,
+            frame_size,
+            width,
+            height,
+            0,
+            0,
+            0,
+            0,
+        ],
+    );
+    chunk(b"LIST", &[116]);
+    chunk(b"strlstrh", &[56]);
+    chunk(b"vidsDIB ", &[0, 0, 0, ONE_FRAME_CYCLES, HZ, 0]);
+    chunk(b"", &[0, frame_size, u32::MAX, 0, 0, 0]); // Length, buffer size, quality, sample size, frame rect
+    chunk(b"strf", &[40]);
+    // Planes and bits per pixel are packed in one u32
+    chunk(
+        b"",
+        &[40, width, height, 1 | 24 << 16, 0, frame_size, 0, 0, 0, 0],
+    );
+    chunk(b"LIST", &[0]);
+    header.extend_from_slice(b"movi");
+    debug_assert_eq!(header.len() as u32, AVI_HEADER_SIZE);
+    writer.write_all(&header)
+}
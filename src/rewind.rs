@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+
+use crate::gb::Gb;
+
+/// Rolling history of save states taken every few frames.
+/// Only the newest state is kept whole, every older one is stored as the run length encoded
+/// difference to the state after it, which is tiny since little changes between frames.
+pub struct Rewind {
+    interval: u32,
+    max_bytes: usize,
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+impl Rewind {
+    /// Takes a snapshot every `interval` frames and drops the oldest ones past `max_bytes`, 0 disables rewinding
+    pub fn new(interval: u32, max_bytes: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            max_bytes,
+            frames: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    /// Call after every emulated frame
+    pub fn push_frame(&mut self, gb: &Gb) {
+        if !self.enabled() {
+            return;
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = gb.save_state();
+        match self.newest.take() {
+            Some(previous) if previous.len() == state.len() => {
+                let delta = encode_delta(&previous, &state);
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            }
+            // Only happens if the state format changes size, start over
+            _ => {
+                self.deltas.clear();
+                self.used = state.len();
+            }
+        }
+        self.newest = Some(state);
+        while self.used > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.used -= oldest.len(),
+                None => {
+                    self.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Loads the newest snapshot and forgets it, so the next call goes further back.
+    /// The oldest snapshot is kept, returns false if there is nothing to load
+    pub fn step_back(&mut self, gb: &mut Gb) -> bool {
+        let newest = match self.newest.take() {
+            Some(n) => n,
+            None => return false,
+        };
+        if let Err(e) = gb.load_state(&newest) {
+            eprintln!("Error rewinding: {}", e);
+            self.clear();
+            return false;
+        }
+        self.frames = 0;
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.used -= delta.len();
+                self.newest = Some(apply_delta(&newest, &delta));
+            }
+            // Keep the oldest snapshot around so holding rewind stays on it
+            None => self.newest = Some(newest),
+        }
+        true
+    }
+}
+
+/// Encodes `old` against `new` as (zero run, literal length, literals) triples of the xor of both
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < old.len() {
+        let zeros_start = i;
+        while i < old.len() && old[i] == new[i] {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < old.len() && old[i] != new[i] {
+            i += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(|j| old[j] ^ new[j]));
+    }
+    out
+}
+
+/// Rebuilds the older state from the newer one and their delta
+fn apply_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut old = new.to_vec();
+    let (mut i, mut d) = (0, 0);
+    while d < delta.len() {
+        i += read_varint(delta, &mut d);
+        let literals = read_varint(delta, &mut d);
+        for byte in old[i..i + literals].iter_mut() {
+            *byte ^= delta[d];
+            d += 1;
+        }
+        i += literals;
+    }
+    old
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gb::test::test_gb;
+
+    fn round_trip(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(old, new);
+        assert_eq!(apply_delta(new, &delta), old);
+        delta
+    }
+
+    #[test]
+    fn identical_states() {
+        let state = vec![0x5A; 1000];
+        assert_eq!(round_trip(&state, &state), [0xE8, 0x07, 0x00]);
+        assert!(round_trip(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn different_states() {
+        let old: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let new: Vec<u8> = old.iter().map(|b| !b).collect();
+        let delta = round_trip(&old, &new);
+        assert_eq!(delta.len(), 1 + 2 + 1000);
+        assert!(delta[3..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn runs_across_varint_boundaries() {
+        for run in [0x7F, 0x80, 0x3FFF, 0x4000, 0x1FFFFF, 0x200000] {
+            let old = vec![0; run * 2 + 3];
+            // An equal run, a changed run of the same length and a single equal byte at the end
+            let mut new = old.clone();
+            new[run..run * 2].fill(1);
+            round_trip(&old, &new);
+            // Changes at both ends and one long equal run in between
+            let mut new = old.clone();
+            new[0] = 1;
+            new[run + 1] = 1;
+            round_trip(&old, &new);
+        }
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX] {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, value);
+            let mut pos = 0;
+            assert_eq!(read_varint(&bytes, &mut pos), value);
+            assert_eq!(pos, bytes.len());
+        }
+    }
+
+    #[test]
+    fn step_back_at_capacity() {
+        let mut gb = test_gb(&[]);
+        let mut states = vec![];
+        for _ in 0..20 {
+            gb.step_frame(0);
+            states.push(gb.save_state());
+        }
+        // Room for the newest state and the deltas back to the fifth newest one
+        let deltas: Vec<usize> = states
+            .windows(2)
+            .map(|w| encode_delta(&w[0], &w[1]).len())
+            .collect();
+        assert!(deltas.iter().all(|&d| d > 0));
+        let limit = states[0].len() + deltas.iter().rev().take(4).sum::<usize>();
+        let mut rewind = Rewind::new(1, limit);
+        for state in &states {
+            gb.load_state(state).unwrap();
+            rewind.push_frame(&gb);
+            assert!(rewind.used <= limit);
+        }
+        assert_eq!(rewind.deltas.len(), 4);
+
+        // Every snapshot that is kept comes back exactly, newest first
+        for state in states.iter().rev().take(5) {
+            assert!(rewind.step_back(&mut gb));
+            assert_eq!(&gb.save_state(), state);
+        }
+        // Then it stays on the oldest one
+        assert!(rewind.step_back(&mut gb));
+        assert_eq!(&gb.save_state(), &states[states.len() - 5]);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    fs::{read, write},
+    path::{Path, PathBuf},
+};
+
+use crate::gb::{state::StateError, Gb};
+
+pub const STATE_SLOTS: u8 = 10;
+
+/// Numbered save state files next to each other, e.g. "tetris.ss0" to "tetris.ss9"
+pub struct StateSlots {
+    dir: PathBuf,
+    name: String,
+}
+
+impl StateSlots {
+    /// States are named after the cartridge file and kept in its directory unless another one is given
+    pub fn new(cart: &Path, dir: Option<PathBuf>) -> Self {
+        let dir = dir.unwrap_or_else(|| {
+            cart.parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from("."))
+        });
+        let name = cart
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "state".to_string());
+        Self { dir, name }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{}.ss{}", self.name, slot))
+    }
+
+    pub fn save(&self, gb: &Gb, slot: u8) -> Result<PathBuf, StateError> {
+        let file = self.path(slot);
+        match write(&file, gb.save_state()) {
+            Ok(()) => Ok(file),
+            Err(e) => Err(StateError::FileError(Box::new(e))),
+        }
+    }
+
+    pub fn load(&self, gb: &mut Gb, slot: u8) -> Result<PathBuf, StateError> {
+        let file = self.path(slot);
+        let bytes = match read(&file) {
+            Ok(b) => b,
+            Err(e) => return Err(StateError::FileError(Box::new(e))),
+        };
+        gb.load_state(&bytes)?;
+        Ok(file)
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    gb::ppu::{LCD_HEIGHT, LCD_WIDTH},
+    palette::Palette,
+};
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    FileError(Box<dyn Error>),
+    Png(png::EncodingError),
+}
+
+impl std::fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenshotError::FileError(e) => write!(f, "File Error: {}!", e),
+            ScreenshotError::Png(e) => write!(f, "Png Error: {}!", e),
+        }
+    }
+}
+
+/// Colors a PPU buffer with a palette, scaling each pixel up to a scale x scale square
+pub fn to_rgba(buf: &[[u8; LCD_WIDTH]; LCD_HEIGHT], palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(LCD_WIDTH * LCD_HEIGHT * scale * scale * 4);
+    for line in buf.iter() {
+        for _ in 0..scale {
+            for &pixel in line.iter() {
+                let color = palette.color(pixel);
+                for _ in 0..scale {
+                    data.extend_from_slice(&color);
+                }
+            }
+        }
+    }
+    data
+}
+
+pub fn save_png(
+    file: &Path,
+    buf: &[[u8; LCD_WIDTH]; LCD_HEIGHT],
+    palette: &Palette,
+    scale: usize,
+) -> Result<(), ScreenshotError> {
+    let writer = match File::create(file) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => return Err(ScreenshotError::FileError(Box::new(e))),
+    };
+    let mut encoder = png::Encoder::new(
+        writer,
+        (LCD_WIDTH * scale) as u32,
+        (LCD_HEIGHT * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(ScreenshotError::Png)?;
+    writer
+        .write_image_data(&to_rgba(buf, palette, scale))
+        .map_err(ScreenshotError::Png)
+}
+
+/// A file name made of the cartridge title and the current UTC time, e.g. "TETRIS_2022-11-20_13-37-00.250.png"
+pub fn timestamped_name(title: &str, extension: &str) -> String {
+    let title: String = title
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let title = if title.is_empty() {
+        "untitled".to_string()
+    } else {
+        title
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{}_{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}.{}",
+        title,
+        year,
+        month,
+        day,
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60,
+        now.subsec_millis(),
+        extension
+    )
+}
+
+/// Picks a timestamped screenshot file in a directory
+pub fn screenshot_path(dir: &Path, title: &str) -> PathBuf {
+    dir.join(timestamped_name(title, "png"))
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date, see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
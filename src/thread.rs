@@ -1,49 +1,274 @@
 use std::{
+    path::PathBuf,
     sync::{
         mpsc::{channel, Receiver, Sender},
         Arc, Mutex,
     },
     thread::{sleep, Builder, JoinHandle},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    debug::DebugViews,
     gb::joypad::Button,
-    gb::ppu::{LCD_HEIGHT, LCD_WIDTH, ONE_FRAME_DURATION},
+    gb::ppu::{Layer, LCD_HEIGHT, LCD_WIDTH, ONE_FRAME_DURATION},
+    gb::watch::Access,
     gb::Gb,
+    movie::{Movie, MoviePlayer, MovieRecorder},
+    recorder::Recorder,
+    rewind::Rewind,
+    savestate::StateSlots,
+    screenshot::timestamped_name,
 };
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// How fast the system runs compared to a real Game Boy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    Times(f32),
+    Uncapped,
+}
+
+impl Speed {
+    /// The speeds stepped through from the keyboard, slowest first
+    pub const PRESETS: [Speed; 7] = [
+        Speed::Times(0.25),
+        Speed::Times(0.5),
+        Speed::Times(1.0),
+        Speed::Times(2.0),
+        Speed::Times(4.0),
+        Speed::Times(8.0),
+        Speed::Uncapped,
+    ];
+
+    /// Parses "uncapped" or a multiplier from 0.25 to 8, with or without a trailing 'x'
+    pub fn parse(s: &str) -> Result<Speed, String> {
+        if s.eq_ignore_ascii_case("uncapped") {
+            return Ok(Speed::Uncapped);
+        }
+        match s.trim_end_matches(['x', 'X']).parse::<f32>() {
+            Ok(times) if (0.25..=8.0).contains(&times) => Ok(Speed::Times(times)),
+            _ => Err("expected a multiplier from 0.25 to 8 or \"uncapped\"".to_string()),
+        }
+    }
+
+    fn frame_duration(&self) -> Option<Duration> {
+        match self {
+            Speed::Times(times) => Some(ONE_FRAME_DURATION.div_f32(*times)),
+            Speed::Uncapped => None,
+        }
+    }
+
+    fn rank(&self) -> f32 {
+        match self {
+            Speed::Times(times) => *times,
+            Speed::Uncapped => f32::INFINITY,
+        }
+    }
+
+    pub fn faster(&self) -> Speed {
+        *Self::PRESETS
+            .iter()
+            .find(|s| s.rank() > self.rank())
+            .unwrap_or(self)
+    }
+
+    pub fn slower(&self) -> Speed {
+        *Self::PRESETS
+            .iter()
+            .rev()
+            .find(|s| s.rank() < self.rank())
+            .unwrap_or(self)
+    }
+}
+
+impl std::fmt::Display for Speed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Speed::Times(times) => write!(f, "{}x", times),
+            Speed::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SystemInput {
     Exit,
     Reset,
     TogglePause,
     JoypadOn(Button),
     JoypadOff(Button),
+    /// Holds a button that is pressed and released every few frames
+    TurboOn(Button),
+    TurboOff(Button),
+    ToggleLayer(Layer),
+    SaveState(u8),
+    LoadState(u8),
+    /// Step back through the rewind history instead of running while true
+    Rewind(bool),
+    SetSpeed(Speed),
+    /// Run at the fast forward speed instead of the normal one while true
+    FastForward(bool),
+    /// Runs a single frame while paused, pauses the system if it is running
+    FrameAdvance,
+    /// Starts recording a movie from the current state or stops and saves the running one
+    ToggleMovieRecording,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SystemEvent {
     ExitNow,
     Frame,
     Serial,
+    /// Emulated frames per second, sent about once a second while running
+    Fps(f32),
+    /// The cpu hit an illegal opcode and hangs until it is reset, sent once when it happens
+    CpuLocked {
+        pc: u16,
+        opcode: u8,
+    },
+    /// An instruction hit a watchpoint, the system pauses right after it
+    Watchpoint {
+        pc: u16,
+        access: Access,
+    },
+}
+
+/// Frames fall this far behind before the pacer gives up catching up and starts over from now
+const MAX_LAG: Duration = Duration::from_millis(100);
+const FPS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Paces frames against an absolute deadline that advances by one frame duration per frame,
+/// so oversleeping one frame makes the next one shorter instead of slowing everything down.
+// TODO: Sync to the audio buffer fill level instead once the APU produces samples
+struct FramePacer {
+    deadline: Option<Instant>,
+    fps_start: Instant,
+    fps_frames: u32,
+}
+
+impl FramePacer {
+    fn new() -> Self {
+        Self {
+            deadline: None,
+            fps_start: Instant::now(),
+            fps_frames: 0,
+        }
+    }
+
+    /// Starts over from now, for when the system was paused or the speed changed
+    fn reset(&mut self) {
+        self.deadline = None;
+        self.fps_start = Instant::now();
+        self.fps_frames = 0;
+    }
+
+    /// Waits for the end of the current frame, None runs as fast as possible
+    fn wait(&mut self, frame_duration: Option<Duration>) {
+        let frame_duration = match frame_duration {
+            Some(d) => d,
+            None => {
+                self.deadline = None;
+                return;
+            }
+        };
+        let now = Instant::now();
+        let deadline = self.deadline.unwrap_or(now) + frame_duration;
+        if deadline > now {
+            sleep(deadline - now);
+            self.deadline = Some(deadline);
+        } else if now - deadline > MAX_LAG {
+            self.deadline = Some(now);
+        } else {
+            self.deadline = Some(deadline);
+        }
+    }
+
+    /// Counts a frame, returns the measured frame rate once per FPS_INTERVAL
+    fn count_frame(&mut self) -> Option<f32> {
+        self.fps_frames += 1;
+        let elapsed = self.fps_start.elapsed();
+        if elapsed >= FPS_INTERVAL {
+            let fps = self.fps_frames as f32 / elapsed.as_secs_f32();
+            self.fps_start = Instant::now();
+            self.fps_frames = 0;
+            Some(fps)
+        } else {
+            None
+        }
+    }
+}
+
+/// Settings the system thread takes ownership of
+pub struct SystemOptions {
+    pub slots: StateSlots,
+    pub rewind: Rewind,
+    pub speed: Speed,
+    pub fast_forward_speed: Speed,
+    /// Only every Nth frame is sent to the window while fast forwarding
+    pub frameskip: u32,
+    /// Played back from its start, the joypad is ignored until it is finished
+    pub movie: Option<Movie>,
+    /// Records a movie from power on
+    pub record_movie: Option<PathBuf>,
+    /// Where movies started with SystemInput::ToggleMovieRecording are saved
+    pub movie_dir: PathBuf,
 }
 
 pub fn system_thread(
     gb: Gb,
     pixels: Arc<Mutex<[[u8; LCD_WIDTH]; LCD_HEIGHT]>>,
+    debug: Arc<Mutex<DebugViews>>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    options: SystemOptions,
 ) -> (JoinHandle<()>, Sender<SystemInput>, Receiver<SystemEvent>) {
     let (input_send, input_recv) = channel();
     let (event_send, event_recv) = channel();
     let handle = Builder::new()
         .name("gb system".to_string())
         .spawn(move || {
-            system_loop(gb, input_recv, event_send, pixels);
+            system_loop(gb, input_recv, event_send, pixels, debug, recorder, options);
         })
         .unwrap_or_else(|_| panic!("Failed to build GB thread"));
     (handle, input_send, event_recv)
 }
 
+/// Hands the current frame to the window and the debug views
+fn publish_frame(
+    gb: &Gb,
+    pixels: &Mutex<[[u8; LCD_WIDTH]; LCD_HEIGHT]>,
+    debug: &Mutex<DebugViews>,
+) {
+    if let Ok(pixel_buf) = pixels.lock().as_deref_mut() {
+        *pixel_buf = gb.get_buf();
+    }
+    if let Ok(debug) = debug.lock().as_deref_mut() {
+        debug.capture(gb);
+    }
+}
+
+fn stop_movie_recording(movie_recorder: &mut Option<MovieRecorder>) {
+    if let Some(r) = movie_recorder.take() {
+        let (file, frames) = (r.path().to_path_buf(), r.frames());
+        match r.finish() {
+            Ok(()) => println!("Saved movie {} ({} frames)", file.display(), frames),
+            Err(e) => eprintln!("Error saving movie: {}", e),
+        }
+    }
+}
+
+/// Anything that changes the state outside of the recorded inputs ends movies
+fn stop_movies(
+    gb: &mut Gb,
+    movie_recorder: &mut Option<MovieRecorder>,
+    movie_player: &mut Option<MoviePlayer>,
+) {
+    stop_movie_recording(movie_recorder);
+    if let Some(player) = movie_player.take() {
+        player.stop(gb);
+        println!("Movie playback stopped");
+    }
+}
+
 /// The System starts paused and must be sent SystemEvent::TogglePause to start it
 /// Sending SystemInput::Exit will cause the thread to exit and send out SystemEvent::ExitNow
 fn system_loop(
@@ -51,54 +276,234 @@ fn system_loop(
     input: Receiver<SystemInput>,
     event: Sender<SystemEvent>,
     pixels: Arc<Mutex<[[u8; LCD_WIDTH]; LCD_HEIGHT]>>,
+    debug: Arc<Mutex<DebugViews>>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    options: SystemOptions,
 ) {
+    let SystemOptions {
+        slots,
+        mut rewind,
+        mut speed,
+        fast_forward_speed,
+        frameskip,
+        movie,
+        record_movie,
+        movie_dir,
+    } = options;
+    let mut fast_forward = false;
+    let mut skipped_frames = 0;
     let mut cycles = 0;
+    // A watchpoint stopped the frame partway, it goes on without starting a new one
+    let mut mid_frame = false;
+    // Buttons held on the joypad, applied at the start of every frame so movies can replay them
+    let mut buttons = 0;
+    let mut turbo = 0;
+    let mut movie_player = None;
+    let mut movie_recorder = None;
+    if let Some(movie) = movie {
+        match MoviePlayer::start(movie, &mut gb) {
+            Ok((player, c)) => {
+                println!("Playing movie ({} frames)", player.frames());
+                movie_player = Some(player);
+                cycles = c;
+            }
+            Err(e) => eprintln!("Error playing movie: {}", e),
+        }
+    } else if let Some(file) = record_movie {
+        gb.reset();
+        println!("Recording movie to {}", file.display());
+        movie_recorder = Some(MovieRecorder::new(&file, &gb, 0, true));
+    }
+    let mut locked = None;
     let mut paused = true;
+    let mut rewinding = false;
+    let mut pacer = FramePacer::new();
     loop {
-        let mut start = Instant::now();
+        let mut advance = false;
         // Handle inputs, block for next event if paused
-        while let Ok(e) = if paused {
+        while let Ok(e) = if paused && !advance {
             input.recv().map_err(|_| ())
         } else {
             input.try_recv().map_err(|_| ())
         } {
             match e {
                 SystemInput::Exit => {
+                    stop_movie_recording(&mut movie_recorder);
                     let _ = event.send(SystemEvent::ExitNow); // Ok if the other thread doesn't care about this event
                     return;
                 }
                 SystemInput::Reset => {
+                    stop_movies(&mut gb, &mut movie_recorder, &mut movie_player);
                     gb.reset();
+                    mid_frame = false;
                 }
                 SystemInput::TogglePause => {
                     if paused {
-                        start = Instant::now();
+                        pacer.reset();
                     }
                     paused = !paused;
                 }
-                SystemInput::JoypadOn(b) => gb.button_press(b),
-                SystemInput::JoypadOff(b) => gb.button_release(b),
+                SystemInput::JoypadOn(b) => buttons |= b.mask(),
+                SystemInput::JoypadOff(b) => buttons &= !b.mask(),
+                SystemInput::TurboOn(b) => turbo |= b.mask(),
+                SystemInput::TurboOff(b) => turbo &= !b.mask(),
+                SystemInput::ToggleLayer(l) => {
+                    let shown = gb.toggle_layer(l);
+                    println!("{:?} layer: {}", l, if shown { "on" } else { "off" });
+                }
+                SystemInput::SaveState(slot) => match slots.save(&gb, slot) {
+                    Ok(file) => println!("Saved state {} to {}", slot, file.display()),
+                    Err(e) => eprintln!("Error saving state {}: {}", slot, e),
+                },
+                SystemInput::LoadState(slot) => match slots.load(&mut gb, slot) {
+                    Ok(file) => {
+                        println!("Loaded state {} from {}", slot, file.display());
+                        stop_movies(&mut gb, &mut movie_recorder, &mut movie_player);
+                        cycles = 0;
+                        mid_frame = false;
+                        // Reported again if the state was locked up too
+                        locked = None;
+                        // Show the loaded frame right away in case the system is paused
+                        publish_frame(&gb, &pixels, &debug);
+                        event
+                            .send(SystemEvent::Frame)
+                            .expect("Failed to send the frame!");
+                    }
+                    Err(e) => eprintln!("Error loading state {}: {}", slot, e),
+                },
+                SystemInput::Rewind(r) => {
+                    rewinding = r && rewind.enabled();
+                    if rewinding {
+                        stop_movies(&mut gb, &mut movie_recorder, &mut movie_player);
+                        locked = None;
+                    }
+                }
+                SystemInput::SetSpeed(s) => {
+                    speed = s;
+                    pacer.reset();
+                }
+                SystemInput::FastForward(f) => {
+                    fast_forward = f;
+                    skipped_frames = 0;
+                    pacer.reset();
+                }
+                SystemInput::FrameAdvance => {
+                    if paused {
+                        advance = true;
+                    } else {
+                        paused = true;
+                    }
+                }
+                SystemInput::ToggleMovieRecording => {
+                    if movie_recorder.is_some() {
+                        stop_movie_recording(&mut movie_recorder);
+                    } else {
+                        stop_movies(&mut gb, &mut movie_recorder, &mut movie_player);
+                        let title = &gb.cart_info().title;
+                        let file = movie_dir.join(timestamped_name(title, "gbm"));
+                        println!("Recording movie to {}", file.display());
+                        movie_recorder = Some(MovieRecorder::new(&file, &gb, cycles, false));
+                        // The rest of a stopped frame is the movie's first frame
+                        mid_frame = false;
+                    }
+                }
             }
         }
 
-        if !paused {
-            // Run CPU
-            cycles = gb.step_frame(cycles);
+        if !paused || advance {
+            if rewinding {
+                // Each step goes back a whole snapshot interval, the frames in between are not shown
+                rewind.step_back(&mut gb);
+                cycles = 0;
+                mid_frame = false;
+            } else {
+                // Movies decide which buttons are held
+                if !mid_frame {
+                    if let Some(player) = movie_player.as_mut() {
+                        player.start_frame(&mut gb);
+                    } else if let Some(r) = movie_recorder.as_mut() {
+                        r.start_frame(&mut gb, buttons, turbo);
+                    } else {
+                        gb.set_buttons(buttons);
+                        gb.set_turbo(turbo);
+                    }
+                }
+                // Run CPU
+                let (c, finished) = gb.step_until_watch(cycles);
+                cycles = c;
+                mid_frame = !finished;
+                if finished {
+                    if let Some(player) = movie_player.as_mut() {
+                        if let Some(frame) = player.end_frame(&gb) {
+                            eprintln!("Movie desynced at frame {}!", frame);
+                        }
+                        if player.finished() {
+                            println!("Movie finished ({} frames)", player.frames());
+                            movie_player.take().unwrap().stop(&mut gb);
+                        }
+                    } else if let Some(r) = movie_recorder.as_mut() {
+                        r.end_frame(&gb);
+                    }
+                    rewind.push_frame(&gb);
+                }
+                if let Some((pc, access)) = gb.take_watch_hit() {
+                    paused = true;
+                    event
+                        .send(SystemEvent::Watchpoint { pc, access })
+                        .expect("Failed to send the watchpoint!");
+                }
+            }
+            if gb.cpu_locked() != locked {
+                locked = gb.cpu_locked();
+                if let Some((pc, opcode)) = locked {
+                    event
+                        .send(SystemEvent::CpuLocked { pc, opcode })
+                        .expect("Failed to send the lockup!");
+                }
+            }
+            // Show where a watchpoint stopped, the rest of the frame is neither recorded nor paced
+            if mid_frame {
+                publish_frame(&gb, &pixels, &debug);
+                event
+                    .send(SystemEvent::Frame)
+                    .expect("Failed to send the frame!");
+                continue;
+            }
+            // Recorded here rather than in the window so no frame is lost when drawing falls behind
+            if let Ok(mut recorder) = recorder.lock() {
+                if let Some(Err(e)) = recorder.as_mut().map(|r| r.frame(&gb.get_buf())) {
+                    eprintln!("Error recording, stopping: {}", e);
+                    if let Some(Err(e)) = recorder.take().map(|r| r.finish()) {
+                        eprintln!("Error finishing recording: {}", e);
+                    }
+                }
+            }
+            // Get next frame and send it, skipping some while fast forwarding so the window keeps up
+            if fast_forward && !advance && skipped_frames + 1 < frameskip {
+                skipped_frames += 1;
+            } else {
+                skipped_frames = 0;
+                publish_frame(&gb, &pixels, &debug);
+                event
+                    .send(SystemEvent::Frame)
+                    .expect("Failed to send the frame!");
+            }
 
-            if let Ok(pixel_buf) = pixels.lock().as_deref_mut() {
-                *pixel_buf = gb.get_buf();
+            if advance {
+                continue;
             }
-            // Get next frame and send it
-            event
-                .send(SystemEvent::Frame)
-                .expect("Failed to send the frame!");
-
-            // Try to make emulation run at gb speed
-            // TODO: Currently sleeps to long, causing the frames to be slow...
-            let elapsed = Instant::now().duration_since(start);
-            if elapsed < ONE_FRAME_DURATION {
-                sleep(ONE_FRAME_DURATION - elapsed);
+            if let Some(fps) = pacer.count_frame() {
+                event
+                    .send(SystemEvent::Fps(fps))
+                    .expect("Failed to send the frame rate!");
             }
+            // Try to make emulation run at the selected speed
+            let current_speed = if fast_forward {
+                fast_forward_speed
+            } else {
+                speed
+            };
+            pacer.wait(current_speed.frame_duration());
         }
     }
 }
@@ -8,14 +8,21 @@ pub fn run_trace(mut gb: Gb, max_cycles: Option<u64>, verbose: bool) {
                 break;
             }
         }
+        let instr = gb.disasm(gb.next_step().0);
         let (pc, op, step_cycles) = gb.step();
         if verbose {
-            println!("({:#06x}): [{:#04x}]\t{}", pc, op, gb.print());
+            println!("({:#06x}): [{:#04x}] {:<20}\t{}", pc, op, instr, gb.print());
         } else {
-            println!("({:#06x}): [{:#04x}]", pc, op);
+            println!("({:#06x}): [{:#04x}] {}", pc, op, instr);
         }
         cycles += step_cycles as u64;
     }
     let (pc, op) = gb.next_step();
-    println!("({:#06x}): [{:#04x}]\t{}", pc, op, gb.print());
+    println!(
+        "({:#06x}): [{:#04x}] {:<20}\t{}",
+        pc,
+        op,
+        gb.disasm(pc),
+        gb.print()
+    );
 }
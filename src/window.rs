@@ -1,5 +1,6 @@
 use std::{
     mem,
+    path::PathBuf,
     sync::{mpsc::Receiver, Arc, Mutex},
     thread::{Builder, JoinHandle},
 };
@@ -7,17 +8,26 @@ use std::{
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
     window::WindowBuilder,
 };
 use winit_input_helper::WinitInputHelper;
 
+#[cfg(feature = "gamepad")]
+use crate::gamepad::Gamepads;
+
 use crate::{
+    bindings::{Action, Bindings},
+    debug::{DebugKind, DebugViews, DebugWindow},
     gb::joypad::Button,
-    gb::ppu::{LCD_HEIGHT, LCD_WIDTH},
+    gb::ppu::{Layer, LCD_HEIGHT, LCD_WIDTH},
     gb::Gb,
-    thread::{system_thread, SystemEvent, SystemInput},
+    palette::Palette,
+    recorder::{RecordFormat, Recorder},
+    savestate::STATE_SLOTS,
+    screenshot::{save_png, screenshot_path, timestamped_name},
+    thread::{system_thread, SystemEvent, SystemInput, SystemOptions},
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -46,12 +56,99 @@ fn relay_thread(
         .unwrap_or_else(|_| panic!("Failed to build relay thread!"))
 }
 
-pub fn launch_window(gb: Gb) {
+fn draw_screen(frame: &mut [u8], buf: &[[u8; LCD_WIDTH]; LCD_HEIGHT], palette: &Palette) {
+    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+        let x = i % LCD_WIDTH;
+        let y = i / LCD_WIDTH;
+
+        pixel.copy_from_slice(&palette.color(buf[y][x]));
+    }
+}
+
+fn start_recording(
+    recorder: &Mutex<Option<Recorder>>,
+    file: &std::path::Path,
+    palette: &Palette,
+    scale: usize,
+) {
+    match Recorder::new(file, palette, scale) {
+        Ok(r) => {
+            println!("Recording to {}", file.display());
+            *recorder.lock().unwrap() = Some(r);
+        }
+        Err(e) => eprintln!("Error starting recording: {}", e),
+    }
+}
+
+fn stop_recording(recorder: &Mutex<Option<Recorder>>) {
+    if let Some(r) = recorder.lock().unwrap().take() {
+        let (file, frames) = (r.path().to_path_buf(), r.frames());
+        match r.finish() {
+            Ok(()) => println!("Saved {} ({} frames)", file.display(), frames),
+            Err(e) => eprintln!("Error finishing recording: {}", e),
+        }
+    }
+}
+
+pub struct WindowOptions {
+    pub palettes: Vec<Palette>,
+    /// Index of the palette used until another one is picked
+    pub palette: usize,
+    pub screenshot_dir: PathBuf,
+    pub screenshot_scale: usize,
+    /// Recording started right away
+    pub record: Option<PathBuf>,
+    /// Format of recordings started with F10
+    pub record_format: RecordFormat,
+    pub bindings: Bindings,
+    /// How far controller sticks have to be pushed, from 0 to 1
+    #[cfg(feature = "gamepad")]
+    pub dead_zone: f32,
+    pub system: SystemOptions,
+}
+
+pub fn launch_window(gb: Gb, options: WindowOptions) {
+    let WindowOptions {
+        palettes,
+        mut palette,
+        screenshot_dir,
+        screenshot_scale,
+        record,
+        record_format,
+        bindings,
+        #[cfg(feature = "gamepad")]
+        dead_zone,
+        system,
+    } = options;
     let pixel_buf: Arc<Mutex<[[u8; LCD_WIDTH]; LCD_HEIGHT]>> =
         Arc::new(Mutex::new([[0; LCD_WIDTH]; LCD_HEIGHT]));
+    let debug_views: Arc<Mutex<DebugViews>> = Arc::new(Mutex::new(DebugViews::default()));
+    let mut debug_windows: Vec<DebugWindow> = vec![];
+    let mut screen_line: Option<usize> = None;
+    let mut slot: u8 = 0;
+    let mut speed = system.speed;
+    let (mut fast_forward_held, mut fast_forward_toggled) = (false, false);
+    let recorder: Arc<Mutex<Option<Recorder>>> = Arc::new(Mutex::new(None));
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = match Gamepads::new(dead_zone) {
+        Ok(g) => Some(g),
+        Err(e) => {
+            eprintln!("Error opening controllers: {}", e);
+            None
+        }
+    };
+    if let Some(file) = record {
+        start_recording(&recorder, &file, &palettes[palette], screenshot_scale);
+    }
     // Init system and thread
     let title = gb.cart_info().title.clone();
-    let (sh, system_input, system_event) = system_thread(gb, pixel_buf.clone());
+    let (sh, system_input, system_event) = system_thread(
+        gb,
+        pixel_buf.clone(),
+        debug_views.clone(),
+        recorder.clone(),
+        system,
+    );
     let mut system_handle = Some(sh);
 
     // Winit + Pixels
@@ -79,12 +176,32 @@ pub fn launch_window(gb: Gb) {
     let exit_event = event_loop.create_proxy();
     let winit_event = event_loop.create_proxy();
     let mut relay_handle = Some(relay_thread(system_event, winit_event));
+    let mut locked = false;
 
     system_input
         .send(SystemInput::TogglePause)
         .expect("Failed to initially unpause the system");
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, target, control_flow| {
         match event {
+            Event::WindowEvent {
+                window_id,
+                event: ref window_event,
+            } if debug_windows.iter().any(|w| w.id() == window_id) => {
+                let index = debug_windows
+                    .iter()
+                    .position(|w| w.id() == window_id)
+                    .unwrap();
+                if debug_windows[index].handle_event(window_event) {
+                    let closed = debug_windows.remove(index);
+                    debug_views.lock().unwrap().close(closed.kind());
+                } else {
+                    debug_windows[index].update(
+                        &debug_views.lock().unwrap(),
+                        screen_line,
+                        &palettes[palette],
+                    );
+                }
+            }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -100,34 +217,54 @@ pub fn launch_window(gb: Gb) {
                 let mut r = None;
                 mem::swap(&mut relay_handle, &mut r);
                 r.unwrap().join().expect("Failed to join relay thread");
+                stop_recording(&recorder);
                 println!("Exiting!");
                 *control_flow = ControlFlow::Exit;
             }
             Event::UserEvent(EventWrapper::SystemEvent(SystemEvent::Frame)) => {
-                let frame = pixels.get_frame_mut();
                 if let Ok(buf) = pixel_buf.lock() {
-                    for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-                        let x = i % LCD_WIDTH;
-                        let y = i / LCD_WIDTH;
-
-                        let slice: [u8; 4] = match buf[y][x] {
-                            /*0 => [0xFF, 0xFF, 0xFF, 0xFF],
-                            1 => [0xCC, 0xCC, 0xCC, 0xFF],
-                            2 => [0x77, 0x77, 0x77, 0xFF],
-                            3 => [0x00, 0x00, 0x00, 0xFF],*/
-                            0 => [255, 239, 206, 0xFF],
-                            1 => [222, 148, 74, 0xFF],
-                            2 => [173, 41, 33, 0xFF],
-                            3 => [49, 24, 82, 0xFF],
-                            _ => unreachable!(),
-                        };
-
-                        pixel.copy_from_slice(&slice);
-                    }
+                    draw_screen(pixels.get_frame_mut(), &buf, &palettes[palette]);
                     window.request_redraw();
                 }
+                let views = debug_views.lock().unwrap();
+                for debug_window in debug_windows.iter_mut() {
+                    debug_window.update(&views, screen_line, &palettes[palette]);
+                }
+            }
+            Event::UserEvent(EventWrapper::SystemEvent(SystemEvent::Fps(fps))) => {
+                let status = if locked { " | CPU locked up" } else { "" };
+                window.set_title(&format!("gb | {} | {:.1} fps{}", title, fps, status));
+            }
+            Event::UserEvent(EventWrapper::SystemEvent(SystemEvent::CpuLocked { pc, opcode })) => {
+                locked = true;
+                eprint!(
+                    "CPU locked up on illegal opcode {:#04x} at {:#06x}",
+                    opcode, pc
+                );
+                match bindings.key(Action::Reset) {
+                    Some(key) => eprintln!(", press {:?} to reset", key),
+                    None => eprintln!(),
+                }
+                window.set_title(&format!("gb | {} | CPU locked up", title));
+            }
+            Event::UserEvent(EventWrapper::SystemEvent(SystemEvent::Watchpoint { pc, access })) => {
+                eprint!("Watchpoint at {:#06x}: {}, paused", pc, access);
+                match bindings.key(Action::Pause) {
+                    Some(key) => eprintln!(", press {:?} to continue", key),
+                    None => eprintln!(),
+                }
+                window.set_title(&format!("gb | {} | Watchpoint at {:#06x}", title, pc));
             }
 
+            Event::RedrawRequested(window_id) if window_id != window.id() => {
+                if let Some(index) = debug_windows.iter().position(|w| w.id() == window_id) {
+                    if let Err(e) = debug_windows[index].render() {
+                        eprintln!("Debug window render failed: {}", e);
+                        let closed = debug_windows.remove(index);
+                        debug_views.lock().unwrap().close(closed.kind());
+                    }
+                }
+            }
             Event::RedrawRequested(_) => {
                 // draw frame
                 if pixels
@@ -144,10 +281,27 @@ pub fn launch_window(gb: Gb) {
             _ => (),
         }
 
-        // Handle input events
-        if input.update(&event) {
+        // Handle input events, debug windows handle their own events above
+        let main_window_event = match &event {
+            Event::WindowEvent { window_id, .. } => *window_id == window.id(),
+            _ => true,
+        };
+        if main_window_event && input.update(&event) {
+            // Actions from the keyboard and any controllers
+            #[allow(unused_mut)]
+            let mut pad_actions: Vec<(Action, bool)> = vec![];
+            #[cfg(feature = "gamepad")]
+            if let Some(gamepads) = gamepads.as_mut() {
+                pad_actions = gamepads.poll(&bindings);
+            }
+            let pressed =
+                |action| bindings.pressed(&input, action) || pad_actions.contains(&(action, true));
+            let released = |action| {
+                bindings.released(&input, action) || pad_actions.contains(&(action, false))
+            };
+
             // Close events
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+            if pressed(Action::Exit) || input.quit() {
                 exit_event
                     .send_event(EventWrapper::Exit)
                     .expect("Could not exit cleanly");
@@ -155,89 +309,168 @@ pub fn launch_window(gb: Gb) {
             }
 
             // Pause emulation
-            if input.key_pressed(VirtualKeyCode::P) {
+            if pressed(Action::Pause) {
                 system_input.send(SystemInput::TogglePause).unwrap();
             }
 
+            // Run a single frame, pausing first if running
+            if pressed(Action::FrameAdvance) {
+                system_input.send(SystemInput::FrameAdvance).unwrap();
+            }
+
             // Reset emulation
-            if input.key_pressed(VirtualKeyCode::R) {
+            if pressed(Action::Reset) {
+                locked = false;
                 system_input.send(SystemInput::Reset).unwrap();
             }
 
-            // Joypad
-            // TODO: Vastly simplify by using a mapping
-            // -> also would allow bindings to change
-            // Directional
-            if input.key_pressed(VirtualKeyCode::W) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Up))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::W) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::Up))
-                    .unwrap();
+            // Screenshot of the last frame
+            if pressed(Action::Screenshot) {
+                let file = screenshot_path(&screenshot_dir, &title);
+                let buf = *pixel_buf.lock().unwrap();
+                match save_png(&file, &buf, &palettes[palette], screenshot_scale) {
+                    Ok(()) => println!("Saved {}", file.display()),
+                    Err(e) => eprintln!("Error saving screenshot: {}", e),
+                }
             }
-            if input.key_pressed(VirtualKeyCode::S) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Down))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::S) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::Down))
-                    .unwrap();
+
+            // Start or stop recording every frame from now on
+            if pressed(Action::Record) {
+                if recorder.lock().unwrap().is_some() {
+                    stop_recording(&recorder);
+                } else {
+                    let file =
+                        screenshot_dir.join(timestamped_name(&title, record_format.extension()));
+                    start_recording(&recorder, &file, &palettes[palette], screenshot_scale);
+                }
             }
-            if input.key_pressed(VirtualKeyCode::A) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Left))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::A) {
+
+            // Start or stop recording a movie of the joypad inputs
+            if pressed(Action::RecordMovie) {
                 system_input
-                    .send(SystemInput::JoypadOff(Button::Left))
+                    .send(SystemInput::ToggleMovieRecording)
                     .unwrap();
             }
-            if input.key_pressed(VirtualKeyCode::D) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Right))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::D) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::Right))
-                    .unwrap();
+
+            // Save states
+            if pressed(Action::SaveState) {
+                system_input.send(SystemInput::SaveState(slot)).unwrap();
             }
-            // Action
-            if input.key_pressed(VirtualKeyCode::Comma) {
-                system_input.send(SystemInput::JoypadOn(Button::B)).unwrap();
-            } else if input.key_released(VirtualKeyCode::Comma) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::B))
-                    .unwrap();
+            if pressed(Action::LoadState) {
+                locked = false;
+                system_input.send(SystemInput::LoadState(slot)).unwrap();
             }
-            if input.key_pressed(VirtualKeyCode::Period) {
-                system_input.send(SystemInput::JoypadOn(Button::A)).unwrap();
-            } else if input.key_released(VirtualKeyCode::Period) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::A))
-                    .unwrap();
+            if pressed(Action::PrevSlot) {
+                slot = (slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                println!("State slot: {}", slot);
             }
-            if input.key_pressed(VirtualKeyCode::Return) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Start))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::Return) {
-                system_input
-                    .send(SystemInput::JoypadOff(Button::Start))
-                    .unwrap();
+            if pressed(Action::NextSlot) {
+                slot = (slot + 1) % STATE_SLOTS;
+                println!("State slot: {}", slot);
             }
-            if input.key_pressed(VirtualKeyCode::RShift) {
-                system_input
-                    .send(SystemInput::JoypadOn(Button::Select))
-                    .unwrap();
-            } else if input.key_released(VirtualKeyCode::RShift) {
+
+            // Rewind while held
+            if pressed(Action::Rewind) {
+                locked = false;
+                system_input.send(SystemInput::Rewind(true)).unwrap();
+            } else if released(Action::Rewind) {
+                system_input.send(SystemInput::Rewind(false)).unwrap();
+            }
+
+            // Fast forward while held or toggled on
+            let fast_forward = fast_forward_held || fast_forward_toggled;
+            if pressed(Action::FastForward) {
+                fast_forward_held = true;
+            } else if released(Action::FastForward) {
+                fast_forward_held = false;
+            }
+            if pressed(Action::ToggleFastForward) {
+                fast_forward_toggled = !fast_forward_toggled;
+            }
+            if fast_forward != (fast_forward_held || fast_forward_toggled) {
                 system_input
-                    .send(SystemInput::JoypadOff(Button::Select))
+                    .send(SystemInput::FastForward(!fast_forward))
                     .unwrap();
             }
 
+            // Speed
+            let new_speed = if pressed(Action::Slower) {
+                speed.slower()
+            } else if pressed(Action::Faster) {
+                speed.faster()
+            } else {
+                speed
+            };
+            if new_speed != speed {
+                speed = new_speed;
+                println!("Speed: {}", speed);
+                system_input.send(SystemInput::SetSpeed(speed)).unwrap();
+            }
+
+            // Layer toggles
+            for layer in [Layer::Background, Layer::Window, Layer::Obj] {
+                if pressed(Action::ToggleLayer(layer)) {
+                    system_input.send(SystemInput::ToggleLayer(layer)).unwrap();
+                }
+            }
+
+            // Cycle palettes, redrawing right away in case the system is paused
+            if pressed(Action::CyclePalette) {
+                palette = (palette + 1) % palettes.len();
+                println!("Palette: {}", palettes[palette].name);
+                if let Ok(buf) = pixel_buf.lock() {
+                    draw_screen(pixels.get_frame_mut(), &buf, &palettes[palette]);
+                }
+                let views = debug_views.lock().unwrap();
+                for debug_window in debug_windows.iter_mut() {
+                    debug_window.update(&views, screen_line, &palettes[palette]);
+                }
+            }
+
+            // Debug views
+            for (action, kind) in [
+                (Action::TileMap, DebugKind::TileMap),
+                (Action::Oam, DebugKind::Oam),
+            ] {
+                if pressed(action) {
+                    if let Some(index) = debug_windows.iter().position(|w| w.kind() == kind) {
+                        debug_windows.remove(index);
+                        debug_views.lock().unwrap().close(kind);
+                    } else {
+                        debug_views.lock().unwrap().open(kind);
+                        debug_windows.push(DebugWindow::new(target, kind, &title));
+                    }
+                }
+            }
+            // The line under the cursor picks which line the OAM view shows
+            let cursor_line = input
+                .mouse()
+                .and_then(|pos| pixels.window_pos_to_pixel(pos).ok())
+                .map(|(_, y)| y);
+            if cursor_line != screen_line {
+                screen_line = cursor_line;
+                let views = debug_views.lock().unwrap();
+                for debug_window in debug_windows.iter_mut() {
+                    debug_window.update(&views, screen_line, &palettes[palette]);
+                }
+            }
+
+            // Joypad
+            for button in Button::ALL {
+                if pressed(Action::Joypad(button)) {
+                    system_input.send(SystemInput::JoypadOn(button)).unwrap();
+                } else if released(Action::Joypad(button)) {
+                    system_input.send(SystemInput::JoypadOff(button)).unwrap();
+                }
+            }
+            for button in [Button::A, Button::B] {
+                if pressed(Action::Turbo(button)) {
+                    system_input.send(SystemInput::TurboOn(button)).unwrap();
+                } else if released(Action::Turbo(button)) {
+                    system_input.send(SystemInput::TurboOff(button)).unwrap();
+                }
+            }
+
             // Resize the window
             if let Some(size) = input.window_resized() {
                 pixels.resize_surface(size.width, size.height);